@@ -0,0 +1,114 @@
+use opencv::core::*;
+use rand::Rng;
+
+use crate::config::AiConfig;
+use crate::detection::Shape;
+use crate::game::Player;
+
+// paddle thickness, and distance from its goal line, in field widths
+const PADDLE_THICKNESS: f32 = 0.015;
+const PADDLE_OFFSET: f32 = 0.03;
+
+pub struct AiPaddle {
+    config: AiConfig,
+    field: Size,
+    x: f32,
+    // paddle center along the goal line, in pixels, and its speed in pixels per second
+    y: f32,
+    velocity: f32,
+    target: f32,
+    // seconds until the ball is looked at again
+    reaction_timer: f32,
+}
+
+impl AiPaddle {
+    pub fn new(config: AiConfig, field: Size) -> AiPaddle {
+        let width = field.width as f32;
+        let x = match config.player {
+            Player::Left => (PADDLE_OFFSET + PADDLE_THICKNESS / 2.0) * width,
+            _ => (1.0 - PADDLE_OFFSET - PADDLE_THICKNESS / 2.0) * width,
+        };
+        AiPaddle {
+            config: config,
+            field: field,
+            x: x,
+            y: field.height as f32 / 2.0,
+            velocity: 0.0,
+            target: field.height as f32 / 2.0,
+            reaction_timer: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, balls: &[(Point2f, Point2f)], elapsed: f32) {
+        // the paddle heads for where it expects the ball, as seen after its
+        // reaction delay and with some aiming error, at a limited speed
+        self.reaction_timer -= elapsed;
+        if self.reaction_timer <= 0.0 {
+            self.reaction_timer = self.config.reaction;
+            self.target = self.aim(balls);
+        }
+        let max_speed = self.config.max_speed * self.field.height as f32;
+        let distance = self.target - self.y;
+        let step = f32::min(distance.abs(), max_speed * elapsed);
+        self.velocity = if elapsed > 0.0 {
+            step * distance.signum() / elapsed
+        } else {
+            0.0
+        };
+        self.y += step * distance.signum();
+        let half = self.config.length * self.field.height as f32 / 2.0;
+        self.y = f32::min(f32::max(self.y, half), self.field.height as f32 - half);
+    }
+
+    fn aim(&self, balls: &[(Point2f, Point2f)]) -> f32 {
+        // intercept of the first ball coming towards the paddle, bouncing on the
+        // top and bottom walls, or the middle of the goal if none comes
+        let height = self.field.height as f32;
+        let mut first: Option<(f32, f32)> = None;
+        for (position, velocity) in balls {
+            if velocity.x == 0.0 {
+                continue;
+            }
+            let time = (self.x - position.x) / velocity.x;
+            if time < 0.0 {
+                continue;
+            }
+            match first {
+                Some((t, _)) if t <= time => {}
+                _ => first = Some((time, position.y + velocity.y * time)),
+            }
+        }
+        let target = match first {
+            Some((_, y)) => {
+                let folded = y.rem_euclid(2.0 * height);
+                if folded > height {
+                    2.0 * height - folded
+                } else {
+                    folded
+                }
+            }
+            None => height / 2.0,
+        };
+        if self.config.error > 0.0 {
+            let error = self.config.error * height;
+            target + rand::thread_rng().gen_range(-error..error)
+        } else {
+            target
+        }
+    }
+
+    pub fn shape(&self) -> Shape {
+        Shape {
+            center: Point2f::new(self.x, self.y),
+            size: Size2f::new(
+                PADDLE_THICKNESS * self.field.width as f32,
+                self.config.length * self.field.height as f32,
+            ),
+            angle: 0.0,
+            velocity: Point2f::new(0.0, self.velocity),
+            measured: true,
+            owner: Some(self.config.player),
+            touching: true,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use opencv::{core::*, highgui::*, prelude::*, videoio::*};
+
+use crate::background::Background;
+use crate::config::Stage;
+use crate::detection::{unwarp, Area};
+use crate::errors::Error;
+use crate::pipeline::{Parameters, Pipeline};
+
+const AUTOTUNE_FRAMES: usize = 20;
+// candidates, from the most to the least sensitive: a wider sigma lowers the
+// low canny threshold, extending the edges
+const BLUR_CANDIDATES: [i32; 4] = [3, 5, 7, 9];
+const MIN_AREA_CANDIDATES: [f64; 6] = [0.0, 50.0, 100.0, 200.0, 400.0, 800.0];
+const SIGMA_CANDIDATES: [f64; 6] = [0.6, 0.5, 0.4, 0.33, 0.25, 0.2];
+
+pub fn autotune(
+    cam: &mut VideoCapture,
+    area: &Area,
+    background: Option<&Background>,
+    mask: &Mat,
+    stages: &[Stage],
+) -> Result<Vec<Stage>, Error> {
+    // samples the empty playing area and picks the most sensitive parameters
+    // detecting no (false) shape in it, or the fewest if none does
+    let mut frames = Vec::with_capacity(AUTOTUNE_FRAMES);
+    let mut frame = Mat::default()?;
+    for _i in 0..AUTOTUNE_FRAMES {
+        cam.read(&mut frame)?;
+        let mut unwarped = unwarp(&frame, area)?;
+        if let Some(b) = background {
+            b.remove_shadows(&mut unwarped)?;
+        }
+        frames.push(unwarped);
+        wait_key(10)?;
+    }
+
+    // most sensitive first: the smallest minimum area, then the lowest blur,
+    // then the widest sigma
+    let mut candidates = Vec::new();
+    for &min_area in MIN_AREA_CANDIDATES.iter() {
+        for &blur in BLUR_CANDIDATES.iter() {
+            for &sigma in SIGMA_CANDIDATES.iter() {
+                candidates.push(Parameters {
+                    sigma: sigma,
+                    blur: blur,
+                    min_area: min_area,
+                });
+            }
+        }
+    }
+    candidates.sort_by(|a, b| {
+        a.min_area
+            .partial_cmp(&b.min_area)
+            .unwrap()
+            .then(a.blur.cmp(&b.blur))
+            .then(b.sigma.partial_cmp(&a.sigma).unwrap())
+    });
+
+    let mut best: Option<(Parameters, usize)> = None;
+    for parameters in candidates {
+        let mut pipeline = Pipeline::new(&parameters.apply(stages))?;
+        let mut false_shapes = 0;
+        for f in frames.iter() {
+            false_shapes += pipeline.detect(f, mask)?.len();
+        }
+        match best {
+            Some((_, fewest)) if fewest <= false_shapes => {}
+            _ => best = Some((parameters, false_shapes)),
+        }
+        if false_shapes == 0 {
+            break;
+        }
+    }
+
+    match best {
+        Some((parameters, _)) => Ok(parameters.apply(stages)),
+        None => Ok(stages.to_vec()),
+    }
+}
@@ -0,0 +1,215 @@
+use opencv::{core::*, highgui::*, imgproc::*, prelude::*, types::*, videoio::*};
+
+use crate::detection::{unwarp, Area};
+use crate::errors::Error;
+
+const BACKGROUND_FRAMES: usize = 30;
+// a shadow darkens the background within this brightness ratio range...
+const SHADOW_MIN_RATIO: f64 = 0.3;
+const SHADOW_MAX_RATIO: f64 = 0.9;
+// ...without changing its chromaticity (normalized r and g) more than this
+const SHADOW_MAX_CHROMA_DIFF: f64 = 0.06;
+// shadow searched around an object to tell whether it touches the surface, in pixels
+const TOUCH_SEARCH: i32 = 40;
+// shadow closer than this to an object is attached to it, in pixels
+const TOUCH_GAP: i32 = 4;
+// below this many shadow pixels around it, the shadow is hidden under the object
+const TOUCH_MIN_SHADOW: i32 = 30;
+// share of the nearby shadow that must be attached to a touching object
+const TOUCH_ATTACHED_SHARE: f64 = 0.2;
+
+pub struct Background {
+    model: Mat,
+    brightness: Mat,
+    chroma: (Mat, Mat),
+}
+
+impl Background {
+    pub fn learn(cam: &mut VideoCapture, area: &Area) -> Result<Background, Error> {
+        // average of a few unwarped frames of the empty playing area
+        let mut sum = Mat::zeros(area.size.height, area.size.width, CV_32FC3)?.to_mat()?;
+        let mut frame = Mat::default()?;
+        for _i in 0..BACKGROUND_FRAMES {
+            cam.read(&mut frame)?;
+            accumulate(&unwarp(&frame, area)?, &mut sum, &no_array()?)?;
+            wait_key(10)?;
+        }
+        let mut model = Mat::default()?;
+        sum.convert_to(&mut model, CV_8UC3, 1.0 / BACKGROUND_FRAMES as f64, 0.0)?;
+
+        Ok(Background {
+            brightness: brightness(&model)?,
+            chroma: chromaticity(&model)?,
+            model: model,
+        })
+    }
+
+    pub fn remove_shadows(&self, img: &mut Mat) -> opencv::Result<Mat> {
+        // shadow pixels are replaced with the background so they produce no contour,
+        // the removed shadows mask is returned
+        let mask = self.shadow_mask(img)?;
+        self.model.copy_to_masked(img, &mask)?;
+
+        Ok(mask)
+    }
+
+    pub fn shadow_mask(&self, img: &Mat) -> opencv::Result<Mat> {
+        let mut ratio = Mat::default()?;
+        divide2(&brightness(img)?, &self.brightness, &mut ratio, 1.0, -1)?;
+        let mut darker = Mat::default()?;
+        in_range(
+            &ratio,
+            &Scalar::all(SHADOW_MIN_RATIO),
+            &Scalar::all(SHADOW_MAX_RATIO),
+            &mut darker,
+        )?;
+
+        let (r, g) = chromaticity(img)?;
+        let mut r_diff = Mat::default()?;
+        let mut g_diff = Mat::default()?;
+        absdiff(&r, &self.chroma.0, &mut r_diff)?;
+        absdiff(&g, &self.chroma.1, &mut g_diff)?;
+        let mut chroma_diff = Mat::default()?;
+        add(&r_diff, &g_diff, &mut chroma_diff, &no_array()?, -1)?;
+        let mut same_chroma = Mat::default()?;
+        in_range(
+            &chroma_diff,
+            &Scalar::all(0.0),
+            &Scalar::all(SHADOW_MAX_CHROMA_DIFF),
+            &mut same_chroma,
+        )?;
+
+        let mut shadow = Mat::default()?;
+        bitwise_and(&darker, &same_chroma, &mut shadow, &no_array()?)?;
+        // shadows are large areas: isolated pixels are dropped, holes are filled
+        let mut cleaned = Mat::default()?;
+        let kernel = get_structuring_element(MORPH_ELLIPSE, Size::new(5, 5), Point::new(-1, -1))?;
+        morphology_ex(
+            &shadow,
+            &mut cleaned,
+            MORPH_OPEN,
+            &kernel,
+            Point::new(-1, -1),
+            1,
+            BORDER_CONSTANT,
+            morphology_default_border_value()?,
+        )?;
+        morphology_ex(
+            &cleaned,
+            &mut shadow,
+            MORPH_CLOSE,
+            &kernel,
+            Point::new(-1, -1),
+            1,
+            BORDER_CONSTANT,
+            morphology_default_border_value()?,
+        )?;
+
+        Ok(shadow)
+    }
+}
+
+fn brightness(img: &Mat) -> opencv::Result<Mat> {
+    // sum of the channels as floats, never zero
+    let mut float = Mat::default()?;
+    img.convert_to(&mut float, CV_32FC3, 1.0, 1.0)?;
+    let mut channels = VectorOfMat::new();
+    split(&float, &mut channels)?;
+    let mut sum = Mat::default()?;
+    let mut total = Mat::default()?;
+    add(
+        &channels.get(0)?,
+        &channels.get(1)?,
+        &mut sum,
+        &no_array()?,
+        -1,
+    )?;
+    add(&sum, &channels.get(2)?, &mut total, &no_array()?, -1)?;
+
+    Ok(total)
+}
+
+fn chromaticity(img: &Mat) -> opencv::Result<(Mat, Mat)> {
+    // normalized red and green: r = R / (R + G + B), g = G / (R + G + B)
+    let total = brightness(img)?;
+    let mut float = Mat::default()?;
+    img.convert_to(&mut float, CV_32FC3, 1.0, 1.0)?;
+    let mut channels = VectorOfMat::new();
+    split(&float, &mut channels)?;
+    let mut r = Mat::default()?;
+    let mut g = Mat::default()?;
+    divide2(&channels.get(2)?, &total, &mut r, 1.0, -1)?;
+    divide2(&channels.get(1)?, &total, &mut g, 1.0, -1)?;
+
+    Ok((r, g))
+}
+
+pub fn is_touching(
+    shadows: &Mat,
+    contours: &VectorOfVectorOfPoint,
+    index: usize,
+) -> opencv::Result<bool> {
+    // an object touching the surface has its shadow hidden under it or attached
+    // to it, the shadow of a hovering object is cast away from it
+    let bounds = bounding_rect(&contours.get(index)?)?;
+    let x = i32::max(0, bounds.x - TOUCH_SEARCH);
+    let y = i32::max(0, bounds.y - TOUCH_SEARCH);
+    let roi = Rect::new(
+        x,
+        y,
+        i32::min(shadows.cols(), bounds.x + bounds.width + TOUCH_SEARCH) - x,
+        i32::min(shadows.rows(), bounds.y + bounds.height + TOUCH_SEARCH) - y,
+    );
+    let mut object = Mat::zeros(roi.height, roi.width, CV_8UC1)?.to_mat()?;
+    draw_contours(
+        &mut object,
+        contours,
+        index as i32,
+        Scalar::all(255.0),
+        FILLED,
+        LINE_8,
+        &no_array()?,
+        i32::MAX,
+        Point::new(-roi.x, -roi.y),
+    )?;
+    let mut outside = Mat::default()?;
+    bitwise_not(&object, &mut outside, &no_array()?)?;
+    let mut shadow = Mat::default()?;
+    bitwise_and(
+        &Mat::roi(shadows, roi)?,
+        &outside,
+        &mut shadow,
+        &no_array()?,
+    )?;
+
+    let around = shadow_around(&object, &shadow, TOUCH_SEARCH)?;
+    if around < TOUCH_MIN_SHADOW {
+        return Ok(true);
+    }
+    let attached = shadow_around(&object, &shadow, TOUCH_GAP)?;
+
+    Ok(attached as f64 >= TOUCH_ATTACHED_SHARE * around as f64)
+}
+
+fn shadow_around(object: &Mat, shadow: &Mat, distance: i32) -> opencv::Result<i32> {
+    // shadow pixels within a distance of the object
+    let kernel = get_structuring_element(
+        MORPH_ELLIPSE,
+        Size::new(2 * distance + 1, 2 * distance + 1),
+        Point::new(-1, -1),
+    )?;
+    let mut near = Mat::default()?;
+    dilate(
+        object,
+        &mut near,
+        &kernel,
+        Point::new(-1, -1),
+        1,
+        BORDER_CONSTANT,
+        morphology_default_border_value()?,
+    )?;
+    let mut shadow_near = Mat::default()?;
+    bitwise_and(shadow, &near, &mut shadow_near, &no_array()?)?;
+
+    count_non_zero(&shadow_near)
+}
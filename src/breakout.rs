@@ -0,0 +1,190 @@
+use c2::Poly;
+use opencv::core::*;
+use std::fs;
+use std::path::Path;
+
+use crate::config::BreakoutConfig;
+use crate::detection::Shape;
+use crate::errors::Error;
+use crate::game::shape_poly;
+
+// gap between two bricks, in pixels
+const BRICK_GAP: f32 = 4.0;
+// largest depth, in field widths: balls are served from the field center and
+// the grid must stay clear of the largest (kids) ball there
+const MAX_DEPTH: f32 = 0.45;
+
+pub struct Breakout {
+    config: BreakoutConfig,
+    field: Size,
+    // hits needed by each cell of each level grid, 0 for no brick
+    layouts: Vec<Vec<Vec<u32>>>,
+    level: usize,
+    // standing bricks and their hits left
+    bricks: Vec<(Shape, Poly)>,
+    hits: Vec<u32>,
+    lives: u32,
+    score: u32,
+}
+
+impl Breakout {
+    pub fn new(config: BreakoutConfig, field: Size, dir: &Path) -> Result<Breakout, Error> {
+        // every level file (relative to the configuration directory) is read at
+        // once so that a bad one is reported at startup
+        if config.depth <= 0.0 || config.depth > MAX_DEPTH {
+            return Err(Error::Config(format!(
+                "breakout depth must be within 0 (excluded) and {}",
+                MAX_DEPTH
+            )));
+        }
+        let mut layouts = Vec::new();
+        for path in config.levels.iter() {
+            let path = dir.join(path);
+            let layout = load_level(&path)?;
+            // bricks must be larger than the gap between them
+            let rows = layout.len() as f32;
+            let columns = layout.iter().map(|r| r.len()).max().unwrap_or(0) as f32;
+            if config.depth * field.width as f32 / columns <= BRICK_GAP
+                || field.height as f32 / rows <= BRICK_GAP
+            {
+                return Err(Error::Config(format!(
+                    "{}: too many bricks for the field",
+                    path.display()
+                )));
+            }
+            layouts.push(layout);
+        }
+        if layouts.is_empty() {
+            return Err(Error::Config("no breakout level".to_string()));
+        }
+        let mut breakout = Breakout {
+            config: config,
+            field: field,
+            layouts: layouts,
+            level: 0,
+            bricks: Vec::new(),
+            hits: Vec::new(),
+            lives: 0,
+            score: 0,
+        };
+        breakout.start();
+        Ok(breakout)
+    }
+
+    pub fn start(&mut self) {
+        self.level = 0;
+        self.lives = self.config.lives;
+        self.score = 0;
+        self.build();
+    }
+
+    fn build(&mut self) {
+        // the level grid fills the far (right) part of the field, rows spread
+        // over its height
+        let layout = &self.layouts[self.level];
+        let rows = layout.len();
+        let columns = layout.iter().map(|r| r.len()).max().unwrap_or(0);
+        self.bricks.clear();
+        self.hits.clear();
+        let width = self.field.width as f32;
+        let left = (1.0 - self.config.depth) * width;
+        let brick_width = self.config.depth * width / columns as f32;
+        let brick_height = self.field.height as f32 / rows as f32;
+        for (row, cells) in layout.iter().enumerate() {
+            for (column, hits) in cells.iter().enumerate() {
+                if *hits == 0 {
+                    continue;
+                }
+                let shape = Shape {
+                    center: Point2f::new(
+                        left + (column as f32 + 0.5) * brick_width,
+                        (row as f32 + 0.5) * brick_height,
+                    ),
+                    size: Size2f::new(brick_width - BRICK_GAP, brick_height - BRICK_GAP),
+                    angle: 0.0,
+                    velocity: Point2f::default(),
+                    measured: false,
+                    owner: None,
+                    touching: true,
+                };
+                self.bricks.push((shape, shape_poly(&shape)));
+                self.hits.push(*hits);
+            }
+        }
+    }
+
+    pub fn bricks(&self) -> &[(Shape, Poly)] {
+        &self.bricks
+    }
+
+    pub fn brick_hits(&self) -> &[u32] {
+        &self.hits
+    }
+
+    pub fn hit(&mut self, index: usize) {
+        // the brick loses a hit, and is cleared (scored) once it has none left
+        self.hits[index] -= 1;
+        if self.hits[index] == 0 {
+            self.bricks.remove(index);
+            self.hits.remove(index);
+            self.score += 1;
+        }
+    }
+
+    pub fn cleared(&self) -> bool {
+        self.bricks.is_empty()
+    }
+
+    pub fn next_level(&mut self) -> bool {
+        // false once the last level is cleared
+        if self.level + 1 >= self.layouts.len() {
+            return false;
+        }
+        self.level += 1;
+        self.build();
+        true
+    }
+
+    pub fn lose_life(&mut self) -> bool {
+        // false once no life is left
+        self.lives = self.lives.saturating_sub(1);
+        self.lives > 0
+    }
+
+    pub fn level(&self) -> usize {
+        self.level + 1
+    }
+
+    pub fn lives(&self) -> u32 {
+        self.lives
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+}
+
+fn load_level(path: &Path) -> Result<Vec<Vec<u32>>, Error> {
+    // one line per row of bricks: '#' is a brick, a digit a brick needing that
+    // many hits, anything else an empty cell
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+    let layout: Vec<Vec<u32>> = content
+        .lines()
+        .map(|line| {
+            line.chars()
+                .map(|c| match c {
+                    '#' => 1,
+                    _ => c.to_digit(10).unwrap_or(0),
+                })
+                .collect()
+        })
+        .collect();
+    if layout.iter().all(|r| r.iter().all(|h| *h == 0)) {
+        return Err(Error::Config(format!(
+            "{}: level without bricks",
+            path.display()
+        )));
+    }
+    Ok(layout)
+}
@@ -0,0 +1,499 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::errors::Error;
+use crate::game::Player;
+use crate::pipeline::Pipeline;
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub detection: DetectionConfig,
+    pub exclusion: ExclusionConfig,
+    pub rules: RulesConfig,
+    pub multiball: MultiballConfig,
+    pub markers: Option<MarkerConfig>,
+    pub aruco: Option<ArucoConfig>,
+    pub ai: Option<AiConfig>,
+    pub breakout: Option<BreakoutConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DetectionConfig {
+    pub mode: DetectorMode,
+    // hand gestures held for a while control the game
+    pub gestures: bool,
+    // removes the shadows cast on the surface before detection
+    pub reject_shadows: bool,
+    // only objects touching the surface (judged from their shadow) hit the ball
+    pub touch_only: bool,
+    // searches the edges pipeline parameters on the empty scene at startup
+    pub autotune: bool,
+    // ordered image processing stages of the edges detection mode
+    // (tables must come last for serialization)
+    pub pipeline: Vec<Stage>,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        DetectionConfig {
+            mode: DetectorMode::default(),
+            gestures: false,
+            reject_shadows: false,
+            touch_only: false,
+            autotune: false,
+            pipeline: default_pipeline(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum Stage {
+    Convert {
+        to: ColorSpace,
+    },
+    // pixels of a colour image with every channel within the bounds
+    InRange {
+        low: [u8; 3],
+        high: [u8; 3],
+    },
+    // gaussian blur, odd kernel size
+    Blur {
+        kernel: i32,
+    },
+    MedianBlur {
+        kernel: i32,
+    },
+    // thresholds are set around the median of the first frame, by +/- sigma
+    Canny {
+        sigma: f64,
+    },
+    Threshold {
+        kind: ThresholdKind,
+        // fixed threshold, or constant subtracted from the mean (adaptive)
+        #[serde(default)]
+        value: f64,
+        // neighbourhood size (adaptive)
+        #[serde(default = "default_block")]
+        block: i32,
+    },
+    Open {
+        kernel: i32,
+    },
+    Close {
+        kernel: i32,
+    },
+    Contours {
+        #[serde(default)]
+        mode: ContourMode,
+        #[serde(default)]
+        min_area: f64,
+    },
+    // how a contour is reduced to a shape
+    Reduce {
+        shape: Reducer,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    Gray,
+    Hsv,
+    Ycrcb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThresholdKind {
+    Binary,
+    BinaryInv,
+    Otsu,
+    Adaptive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContourMode {
+    External,
+    List,
+}
+
+impl Default for ContourMode {
+    fn default() -> Self {
+        ContourMode::External
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reducer {
+    MinAreaRect,
+    BoundingRect,
+    Ellipse,
+}
+
+pub fn default_pipeline() -> Vec<Stage> {
+    // gray, 3x3 gaussian blur, canny, external contours, minimum area rectangles
+    vec![
+        Stage::Convert {
+            to: ColorSpace::Gray,
+        },
+        Stage::Blur { kernel: 3 },
+        Stage::Canny { sigma: 0.3 },
+        Stage::Contours {
+            mode: ContourMode::External,
+            min_area: 0.0,
+        },
+        Stage::Reduce {
+            shape: Reducer::MinAreaRect,
+        },
+    ]
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExclusionConfig {
+    // shapes present at startup in the empty area are ignored
+    pub learn_static: bool,
+    // areas ignored by detection, vertices relative to the playing area (0.0 to 1.0)
+    pub polygons: Vec<Vec<[f32; 2]>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RulesConfig {
+    // points needed to win a set
+    pub target: u32,
+    // lead over the opponent needed to win a set
+    pub win_by: u32,
+    // the match is played in the best of this many sets
+    pub sets: u32,
+    // seconds counted down before each serve
+    pub countdown: f32,
+    // seconds the winner is shown before the rematch
+    pub game_over: f32,
+    pub difficulty: Difficulty,
+    // 2, or 4 with a goal on every edge of the field
+    pub players: u32,
+    // four players only: goals conceded before elimination
+    pub lives: u32,
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        RulesConfig {
+            target: 11,
+            win_by: 2,
+            sets: 1,
+            countdown: 3.0,
+            game_over: 5.0,
+            difficulty: Difficulty::default(),
+            players: 2,
+            lives: 5,
+        }
+    }
+}
+
+impl RulesConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.players != 2 && self.players != 4 {
+            return Err(Error::Config(format!(
+                "rules players must be 2 or 4, not {}",
+                self.players
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AiConfig {
+    // side of the computer opponent, left or right
+    pub player: Player,
+    // seconds between two looks at the ball
+    pub reaction: f32,
+    // paddle speed, in field heights per second
+    pub max_speed: f32,
+    // aiming error range, in field heights
+    pub error: f32,
+    // paddle length, in field heights
+    pub length: f32,
+}
+
+impl AiConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        // the paddle moves along the left or right goal line
+        if self.player != Player::Left && self.player != Player::Right {
+            return Err(Error::Config(format!(
+                "ai player must be left or right, not {}",
+                self.player.name()
+            )));
+        }
+        if self.length <= 0.0 || self.length > 1.0 {
+            return Err(Error::Config(
+                "ai length must be within 0 (excluded) and 1".to_string(),
+            ));
+        }
+        if self.max_speed < 0.0 || self.reaction < 0.0 {
+            return Err(Error::Config(
+                "ai max_speed and reaction can't be negative".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        AiConfig {
+            player: Player::Right,
+            reaction: 0.2,
+            max_speed: 0.8,
+            error: 0.05,
+            length: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BreakoutConfig {
+    // level files, played in order
+    pub levels: Vec<String>,
+    pub lives: u32,
+    // part of the field filled by the bricks, from the right edge, in field widths
+    pub depth: f32,
+}
+
+impl Default for BreakoutConfig {
+    fn default() -> Self {
+        BreakoutConfig {
+            levels: vec![
+                "levels/1.txt".to_string(),
+                "levels/2.txt".to_string(),
+                "levels/3.txt".to_string(),
+            ],
+            lives: 3,
+            depth: 0.4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MultiballConfig {
+    // balls served at once
+    pub balls: u32,
+    // an extra ball is spawned every this many seconds of play (0 never)
+    pub spawn_every: f32,
+    // an extra ball is spawned every this many paddle hits (0 never)
+    pub spawn_hits: u32,
+    pub max_balls: u32,
+}
+
+impl MultiballConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.balls > self.max_balls {
+            return Err(Error::Config(format!(
+                "multiball balls ({}) can't exceed max_balls ({})",
+                self.balls, self.max_balls
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for MultiballConfig {
+    fn default() -> Self {
+        MultiballConfig {
+            balls: 1,
+            spawn_every: 0.0,
+            spawn_hits: 0,
+            max_balls: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    // slow and large ball
+    Kids,
+    Normal,
+    // fast and small ball, quickly speeding up
+    Expert,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name {
+            "kids" => Some(Difficulty::Kids),
+            "normal" => Some(Difficulty::Normal),
+            "expert" => Some(Difficulty::Expert),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Difficulty::Kids => "kids",
+            Difficulty::Normal => "normal",
+            Difficulty::Expert => "expert",
+        }
+    }
+
+    pub fn harder(&self) -> Difficulty {
+        match *self {
+            Difficulty::Kids => Difficulty::Normal,
+            _ => Difficulty::Expert,
+        }
+    }
+
+    pub fn easier(&self) -> Difficulty {
+        match *self {
+            Difficulty::Expert => Difficulty::Normal,
+            _ => Difficulty::Kids,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectorMode {
+    // canny edges and contours of anything on the surface
+    Edges,
+    // skin colored regions only (hands and arms), calibrated at startup
+    Skin,
+    // moving regions from dense optical flow, measuring their motion
+    Flow,
+}
+
+impl Default for DetectorMode {
+    fn default() -> Self {
+        DetectorMode::Edges
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MarkerConfig {
+    // share of a shape's bounding box that must be covered by a marker color
+    #[serde(default = "default_coverage")]
+    pub min_coverage: f64,
+    #[serde(default)]
+    pub rule: PaddleRule,
+    pub left: HsvRange,
+    pub right: HsvRange,
+    // four players mode
+    pub top: Option<HsvRange>,
+    pub bottom: Option<HsvRange>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct HsvRange {
+    // opencv ranges: hue in 0..180, saturation and value in 0..256
+    // a low hue greater than the high hue wraps around (reds)
+    pub low: [u8; 3],
+    pub high: [u8; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaddleRule {
+    // tagged shapes hit the ball anywhere, hits are credited to their owner
+    Anywhere,
+    // tagged shapes only hit the ball in their owner's half
+    OwnHalf,
+}
+
+impl Default for PaddleRule {
+    fn default() -> Self {
+        PaddleRule::OwnHalf
+    }
+}
+
+impl MarkerConfig {
+    pub fn range(&self, player: Player) -> Option<&HsvRange> {
+        match player {
+            Player::Left => Some(&self.left),
+            Player::Right => Some(&self.right),
+            Player::Top => self.top.as_ref(),
+            Player::Bottom => self.bottom.as_ref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ArucoConfig {
+    // predefined dictionary, e.g. "4x4_50"
+    #[serde(default = "default_dictionary")]
+    pub dictionary: String,
+    pub paddles: Vec<PaddleConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PaddleConfig {
+    pub id: i32,
+    pub player: Option<Player>,
+    // paddle geometry in marker side lengths, along the marker axes
+    pub width: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub offset: [f32; 2],
+}
+
+fn default_block() -> i32 {
+    11
+}
+
+fn default_coverage() -> f64 {
+    0.1
+}
+
+fn default_dictionary() -> String {
+    "4x4_50".to_string()
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        let content = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&content).map_err(|e| Error::Config(e.to_string()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        // values the game can't run with, beyond what deserialization checks
+        if self.detection.mode == DetectorMode::Edges {
+            Pipeline::validate(&self.detection.pipeline)?;
+        }
+        self.rules.validate()?;
+        self.multiball.validate()?;
+        if let Some(ref ai) = self.ai {
+            ai.validate()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = toml::to_string(self).map_err(|e| Error::Config(e.to_string()))?;
+        fs::write(path, content)?;
+
+        Ok(())
+    }
+}
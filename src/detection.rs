@@ -1,9 +1,13 @@
 use opencv::{core::*, highgui::*, imgproc::*, prelude::*, types::*, videoio::*};
 use std::cmp::*;
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::errors::{Error, ErrorKind};
+use crate::flow::FlowDetector;
+use crate::game::Player;
+use crate::pipeline::{reduce_contours, Pipeline};
 use crate::utils::*;
 
 #[derive(Debug, Clone)]
@@ -14,11 +18,158 @@ pub struct Area {
     pub unwarped_mat: Mat,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct Shape {
+    pub center: Point2f,
+    pub size: Size2f,
+    pub angle: f32,
+    pub velocity: Point2f,
+    // velocity measured by the detector rather than derived from tracking
+    pub measured: bool,
+    pub owner: Option<Player>,
+    // whether the object touches the surface (or is hovering above it)
+    pub touching: bool,
+}
+
+impl Shape {
+    pub fn from_rect(rect: &RotatedRect) -> Shape {
+        Shape {
+            center: rect.center(),
+            size: rect.size(),
+            angle: rect.angle(),
+            velocity: Point2f::default(),
+            measured: false,
+            owner: None,
+            touching: true,
+        }
+    }
+
+    pub fn scale(&self, x_ratio: f64, y_ratio: f64) -> Shape {
+        Shape {
+            center: Point2f::new(
+                self.center.x * x_ratio as f32,
+                self.center.y * y_ratio as f32,
+            ),
+            size: Size2f::new(
+                self.size.width * x_ratio as f32,
+                self.size.height * y_ratio as f32,
+            ),
+            velocity: Point2f::new(
+                self.velocity.x * x_ratio as f32,
+                self.velocity.y * y_ratio as f32,
+            ),
+            ..*self
+        }
+    }
+
+    pub fn vertices(&self) -> [Point2f; 4] {
+        // same corner order as RotatedRect::points
+        let b = (self.angle as f64).to_radians();
+        let a = (b.sin() * 0.5) as f32;
+        let b = (b.cos() * 0.5) as f32;
+        let (w, h) = (self.size.width, self.size.height);
+        let p0 = Point2f::new(self.center.x - a * h - b * w, self.center.y + b * h - a * w);
+        let p1 = Point2f::new(self.center.x + a * h - b * w, self.center.y - b * h - a * w);
+        let p2 = Point2f::new(2.0 * self.center.x - p0.x, 2.0 * self.center.y - p0.y);
+        let p3 = Point2f::new(2.0 * self.center.x - p1.x, 2.0 * self.center.y - p1.y);
+
+        [p0, p1, p2, p3]
+    }
+
+    pub fn overlap(&self, other: &Shape) -> f32 {
+        // intersection area of both axis-aligned bounding boxes
+        let (a, b) = (self.bounds(), other.bounds());
+        let width = f32::min(a.2, b.2) - f32::max(a.0, b.0);
+        let height = f32::min(a.3, b.3) - f32::max(a.1, b.1);
+        if width <= 0.0 || height <= 0.0 {
+            0.0
+        } else {
+            width * height
+        }
+    }
+
+    pub fn bounding_rect(&self) -> Rect {
+        let (x0, y0, x1, y1) = self.bounds();
+        Rect::new(
+            x0.floor() as i32,
+            y0.floor() as i32,
+            (x1 - x0).ceil() as i32,
+            (y1 - y0).ceil() as i32,
+        )
+    }
+
+    fn bounds(&self) -> (f32, f32, f32, f32) {
+        // (min x, min y, max x, max y)
+        self.vertices().iter().fold(
+            (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+            |(x0, y0, x1, y1), p| (x0.min(p.x), y0.min(p.y), x1.max(p.x), y1.max(p.y)),
+        )
+    }
+}
+
 enum ScreenColor {
     Black,
     White,
 }
 
+const LATENCY_SAMPLES: usize = 5;
+const LATENCY_BRIGHTNESS_STEP: f64 = 30.0;
+const LATENCY_TIMEOUT: Duration = Duration::from_secs(2);
+
+const SKIN_CALIBRATION_DELAY: Duration = Duration::from_secs(5);
+// accepted deviation from the sampled skin chrominance, in standard deviations
+const SKIN_TOLERANCE: f64 = 2.5;
+const SKIN_MIN_LUMA: f64 = 30.0;
+const SKIN_MIN_AREA: f64 = 150.0;
+
+pub enum Detector {
+    Edges(Pipeline),
+    Skin(SkinDetector),
+    Flow(FlowDetector),
+}
+
+impl Detector {
+    pub fn detect(
+        &mut self,
+        img: &Mat,
+        mask: &Mat,
+    ) -> Result<VectorOfVectorOfPoint, opencv::Error> {
+        // contours are only searched where the (optional) mask is set
+        match self {
+            Detector::Edges(pipeline) => pipeline.detect(img, mask),
+            Detector::Skin(skin) => skin.detect(img, mask),
+            Detector::Flow(flow) => flow.detect(img, mask),
+        }
+    }
+
+    pub fn shapes(
+        &self,
+        contours: &VectorOfVectorOfPoint,
+    ) -> Result<VectorOfRotatedRect, opencv::Error> {
+        match self {
+            Detector::Edges(pipeline) => reduce_contours(contours, pipeline.reducer()),
+            _ => contour_shapes(contours),
+        }
+    }
+
+    pub fn motion(
+        &self,
+        contours: &VectorOfVectorOfPoint,
+        index: usize,
+    ) -> Result<Option<Point2f>, opencv::Error> {
+        // motion measured by the detector itself for a contour, if any
+        match self {
+            Detector::Flow(flow) => Ok(Some(flow.motion(contours, index)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+pub struct SkinDetector {
+    low: Scalar,
+    high: Scalar,
+}
+
 pub struct ThreshCacher
 {
     sigma: f64,
@@ -35,7 +186,7 @@ impl ThreshCacher
             median: get_median,
         }
     }
-    fn values(&mut self, mat: &Mat) -> (f64, f64) {
+    pub(crate) fn values(&mut self, mat: &Mat) -> (f64, f64) {
         match self.thresholds {
             Some(t) => t,
             None => {
@@ -54,18 +205,137 @@ pub fn get_unwarped_areas(
     projector_res: Size,
     fullscreen: bool,
 ) -> Result<(Area, Area), Error> {
+    let (tx, _shown, calibration) = spawn_calibration_screen(projector_res);
+    let mut tmp = Mat::default()?;
+    for _i in 0..30 {
+        cam.read(&mut tmp)?;
+    }
+    let screen = match detect_playing_area(cam, fullscreen) {
+        Err(r) => return Err(r),
+        Ok(a) => a,
+    };
+    let area;
+    if !fullscreen {
+        // playing area does not equals screen area -> area detection needed
+        tx.send(Some(ScreenColor::Black)).unwrap();
+        for _i in 0..30 {
+            cam.read(&mut tmp)?;
+        }
+        area = match detect_playing_area(cam, true) {
+            Err(_) => panic!("Can't detect area"),
+            Ok(a) => a,
+        };
+        if area.size.width > screen.size.width || area.size.height > screen.size.height {
+            return Err(Error::DetectionError(ErrorKind::AreaBiggerThanScreen));
+        }
+    } else {
+        area = screen.clone();
+    }
+
+    tx.send(None).unwrap();
+    let _res = calibration.join();
+
+    Ok((screen, area))
+}
+
+pub fn measure_latency(cam: &mut VideoCapture, projector_res: Size) -> Result<Duration, Error> {
+    // projects white flashes over a black screen and times their appearance in the
+    // camera frames: the median of the samples is the display to camera latency
+    let (tx, shown, calibration) = spawn_calibration_screen(projector_res);
+    // the screen is closed on every exit path
+    let screen = CalibrationScreen {
+        tx: tx,
+        shown: shown,
+        thread: Some(calibration),
+    };
+    screen.show(ScreenColor::Black)?;
+    let mut tmp = Mat::default()?;
+    for _i in 0..30 {
+        cam.read(&mut tmp)?;
+    }
+    let mut samples = Vec::with_capacity(LATENCY_SAMPLES);
+    for _i in 0..LATENCY_SAMPLES {
+        let baseline = get_brightness(cam)?;
+        let flash = screen.show(ScreenColor::White)?;
+        loop {
+            if get_brightness(cam)? > baseline + LATENCY_BRIGHTNESS_STEP {
+                samples.push(flash.elapsed());
+                break;
+            }
+            if flash.elapsed() > LATENCY_TIMEOUT {
+                return Err(Error::DetectionError(ErrorKind::FlashNotDetected));
+            }
+        }
+        screen.show(ScreenColor::Black)?;
+        for _i in 0..10 {
+            cam.read(&mut tmp)?;
+        }
+    }
+    drop(screen);
+    samples.sort();
+
+    Ok(samples[samples.len() / 2])
+}
+
+fn get_brightness(cam: &mut VideoCapture) -> opencv::Result<f64> {
+    let mut frame = Mat::default()?;
+    cam.read(&mut frame)?;
+    let mut gray = Mat::default()?;
+    cvt_color(&frame, &mut gray, COLOR_BGR2GRAY, 0)?;
+    let m = mean(&gray, &no_array()?)?;
+
+    Ok(m[0])
+}
+
+struct CalibrationScreen {
+    tx: Sender<Option<ScreenColor>>,
+    shown: Receiver<Instant>,
+    thread: Option<thread::JoinHandle<opencv::Result<()>>>,
+}
+
+impl CalibrationScreen {
+    fn show(&self, color: ScreenColor) -> opencv::Result<Instant> {
+        // instant the color is displayed, the window may have been closed by a key
+        let closed = || opencv::Error::new(StsError, "calibration window closed".to_string());
+        self.tx.send(Some(color)).map_err(|_| closed())?;
+        self.shown.recv().map_err(|_| closed())
+    }
+}
+
+impl Drop for CalibrationScreen {
+    fn drop(&mut self) {
+        let _res = self.tx.send(None);
+        if let Some(thread) = self.thread.take() {
+            let _res = thread.join();
+        }
+    }
+}
+
+fn spawn_calibration_screen(
+    projector_res: Size,
+) -> (
+    Sender<Option<ScreenColor>>,
+    Receiver<Instant>,
+    thread::JoinHandle<opencv::Result<()>>,
+) {
+    // fullscreen window switching between white and black on request,
+    // the instant each requested color is displayed is sent back
     let (tx, rx) = channel();
+    let (shown_tx, shown_rx) = channel();
     let calibration = thread::spawn(move || -> opencv::Result<()> {
         let mut calibration =
             Mat::zeros(projector_res.height, projector_res.width, CV_8UC3)?.to_mat()?;
         calibration.set_to(&Scalar::new(255.0, 255.0, 255.0, 0.0), &no_array()?)?;
         loop {
+            let mut changed = false;
             match rx.try_recv() {
                 Ok(Some(ScreenColor::Black)) => {
                     calibration.set_to(&Scalar::new(0.0, 0.0, 0.0, 0.0), &no_array()?)?;
+                    changed = true;
                 }
                 Ok(Some(ScreenColor::White)) => {
                     calibration.set_to(&Scalar::new(255.0, 255.0, 255.0, 0.0), &no_array()?)?;
+                    changed = true;
                 }
                 Ok(None) => {
                     destroy_frame("calibration")?;
@@ -76,6 +346,9 @@ pub fn get_unwarped_areas(
             show_frame("calibration", &calibration)?;
 
             let key = wait_key(10)?;
+            if changed {
+                let _res = shown_tx.send(Instant::now());
+            }
             if key > 0 && key != 255 {
                 destroy_frame("calibration")?;
                 break;
@@ -84,36 +357,8 @@ pub fn get_unwarped_areas(
 
         Ok(())
     });
-    let mut tmp = Mat::default()?;
-    for _i in 0..30 {
-        cam.read(&mut tmp)?;
-    }
-    let screen = match detect_playing_area(cam, fullscreen) {
-        Err(r) => return Err(r),
-        Ok(a) => a,
-    };
-    let area;
-    if !fullscreen {
-        // playing area does not equals screen area -> area detection needed
-        tx.send(Some(ScreenColor::Black)).unwrap();
-        for _i in 0..30 {
-            cam.read(&mut tmp)?;
-        }
-        area = match detect_playing_area(cam, true) {
-            Err(_) => panic!("Can't detect area"),
-            Ok(a) => a,
-        };
-        if area.size.width > screen.size.width || area.size.height > screen.size.height {
-            return Err(Error::DetectionError(ErrorKind::AreaBiggerThanScreen));
-        }
-    } else {
-        area = screen.clone();
-    }
 
-    tx.send(None).unwrap();
-    let _res = calibration.join();
-
-    Ok((screen, area))
+    (tx, shown_rx, calibration)
 }
 
 pub fn detect_playing_area(cam: &mut VideoCapture, perspective: bool) -> Result<Area, Error> {
@@ -259,40 +504,10 @@ fn get_destination_corners(src_vec: &VectorOfPoint2f) -> Result<VectorOfPoint2f,
     Ok(dst)
 }
 
-pub fn shape_detect(img: &Mat, thresholds: &mut ThreshCacher) -> Result<VectorOfRotatedRect, opencv::Error> {
-    // contour detection
-    // mat priming: channels to gray, gaussian blur then canny on fixed threshold
+pub fn contour_shapes(
+    contours: &VectorOfVectorOfPoint,
+) -> Result<VectorOfRotatedRect, opencv::Error> {
     let mut rect = VectorOfRotatedRect::new();
-    let mut gray = Mat::default()?;
-    let mut blurred = Mat::default()?;
-    let mut canny_output = Mat::default()?;
-    cvt_color(&img, &mut gray, COLOR_BGR2GRAY, 0)?;
-    gaussian_blur(
-        &gray,
-        &mut blurred,
-        Size::new(3, 3),
-        0.0,
-        0.0,
-        BORDER_DEFAULT,
-    )?;
-
-    let (threshold_min, threshold_max) = thresholds.values(&blurred); 
-    canny(
-        &blurred,
-        &mut canny_output,
-        threshold_min,
-        threshold_max,
-        3,
-        false,
-    )?;
-    let mut contours = VectorOfVectorOfPoint::new();
-    find_contours(
-        &canny_output,
-        &mut contours,
-        RETR_EXTERNAL,
-        CHAIN_APPROX_SIMPLE,
-        Point::new(0, 0),
-    )?;
     for index in 0..contours.len() {
         let c = contours.get(index)?;
         rect.push(min_area_rect(&c)?);
@@ -301,20 +516,163 @@ pub fn shape_detect(img: &Mat, thresholds: &mut ThreshCacher) -> Result<VectorOf
     Ok(rect)
 }
 
-pub fn scale_shape(
-    shape: &RotatedRect,
-    x_ratio: f64,
-    y_ratio: f64,
-) -> Result<RotatedRect, opencv::Error> {
-    let scaled_center = Point2f::new(
-        shape.center().x * x_ratio as f32,
-        shape.center().y * y_ratio as f32,
-    );
-    let scaled_size = Size2f::new(
-        shape.size().width * x_ratio as f32,
-        shape.size().height * y_ratio as f32,
-    );
-    return RotatedRect::new(scaled_center, scaled_size, shape.angle());
+impl SkinDetector {
+    pub fn calibrate(
+        cam: &mut VideoCapture,
+        area: &Area,
+        projector_res: Size,
+        game_roi: Rect,
+    ) -> Result<SkinDetector, Error> {
+        // projects a square in the middle of the playing area in which a hand
+        // is placed, then samples its YCrCb chrominance
+        let mut target =
+            Mat::zeros(projector_res.height, projector_res.width, CV_8UC3)?.to_mat()?;
+        let side = game_roi.height / 4;
+        let square = Rect::new(
+            game_roi.x + (game_roi.width - side) / 2,
+            game_roi.y + (game_roi.height - side) / 2,
+            side,
+            side,
+        );
+        rectangle(
+            &mut target,
+            square,
+            Scalar::new(255.0, 255.0, 255.0, 0.0),
+            3,
+            LINE_8,
+            0,
+        )?;
+        put_text(
+            &mut target,
+            "place a hand in the square",
+            Point::new(square.x, square.y - 20),
+            FONT_HERSHEY_SIMPLEX,
+            1.0,
+            Scalar::new(255.0, 255.0, 255.0, 0.0),
+            2,
+            LINE_8,
+            false,
+        )?;
+        let start = Instant::now();
+        let mut frame = Mat::default()?;
+        while start.elapsed() < SKIN_CALIBRATION_DELAY {
+            cam.read(&mut frame)?;
+            show_frame("calibration", &target)?;
+            wait_key(10)?;
+        }
+        destroy_frame("calibration")?;
+
+        let unwarped = unwarp(&frame, area)?;
+        // inner part of the square only, avoiding the projected outline
+        let sample_side = (unwarped.rows() as f64 / 4.0 * 0.6) as i32;
+        let sample = Mat::roi(
+            &unwarped,
+            Rect::new(
+                (unwarped.cols() - sample_side) / 2,
+                (unwarped.rows() - sample_side) / 2,
+                sample_side,
+                sample_side,
+            ),
+        )?;
+        let mut ycrcb = Mat::default()?;
+        cvt_color(&sample, &mut ycrcb, COLOR_BGR2YCrCb, 0)?;
+        let mut mean = Mat::default()?;
+        let mut stddev = Mat::default()?;
+        mean_std_dev(&ycrcb, &mut mean, &mut stddev, &no_array()?)?;
+        let (cr, cb) = (*mean.at::<f64>(1)?, *mean.at::<f64>(2)?);
+        let (cr_dev, cb_dev) = (*stddev.at::<f64>(1)?, *stddev.at::<f64>(2)?);
+
+        Ok(SkinDetector {
+            low: Scalar::new(
+                SKIN_MIN_LUMA,
+                cr - SKIN_TOLERANCE * cr_dev,
+                cb - SKIN_TOLERANCE * cb_dev,
+                0.0,
+            ),
+            high: Scalar::new(
+                255.0,
+                cr + SKIN_TOLERANCE * cr_dev,
+                cb + SKIN_TOLERANCE * cb_dev,
+                0.0,
+            ),
+        })
+    }
+
+    pub fn detect(&self, img: &Mat, mask: &Mat) -> Result<VectorOfVectorOfPoint, opencv::Error> {
+        // skin segmentation: chrominance range, opening (noise) then closing (holes)
+        let mut ycrcb = Mat::default()?;
+        let mut skin = Mat::default()?;
+        let mut opened = Mat::default()?;
+        let mut closed = Mat::default()?;
+        cvt_color(&img, &mut ycrcb, COLOR_BGR2YCrCb, 0)?;
+        in_range(&ycrcb, &self.low, &self.high, &mut skin)?;
+        let kernel = get_structuring_element(MORPH_ELLIPSE, Size::new(5, 5), Point::new(-1, -1))?;
+        morphology_ex(
+            &skin,
+            &mut opened,
+            MORPH_OPEN,
+            &kernel,
+            Point::new(-1, -1),
+            1,
+            BORDER_CONSTANT,
+            morphology_default_border_value()?,
+        )?;
+        morphology_ex(
+            &opened,
+            &mut closed,
+            MORPH_CLOSE,
+            &kernel,
+            Point::new(-1, -1),
+            2,
+            BORDER_CONSTANT,
+            morphology_default_border_value()?,
+        )?;
+        apply_mask(&mut closed, mask)?;
+        let mut contours = VectorOfVectorOfPoint::new();
+        find_contours(
+            &closed,
+            &mut contours,
+            RETR_EXTERNAL,
+            CHAIN_APPROX_SIMPLE,
+            Point::new(0, 0),
+        )?;
+        let mut hands = VectorOfVectorOfPoint::new();
+        for index in 0..contours.len() {
+            let c = contours.get(index)?;
+            if contour_area(&c, false)? >= SKIN_MIN_AREA {
+                hands.push(c);
+            }
+        }
+
+        Ok(hands)
+    }
+}
+
+pub(crate) fn apply_mask(binary: &mut Mat, mask: &Mat) -> Result<(), opencv::Error> {
+    // an empty mask keeps everything
+    if mask.empty()? {
+        return Ok(());
+    }
+    let mut masked = Mat::default()?;
+    bitwise_and(&*binary, mask, &mut masked, &no_array()?)?;
+    *binary = masked;
+
+    Ok(())
+}
+
+pub fn unwarp(frame: &Mat, area: &Area) -> Result<Mat, opencv::Error> {
+    let mut unwarped = Mat::default()?;
+    warp_perspective(
+        frame,
+        &mut unwarped,
+        &area.unwarped_mat,
+        area.size,
+        INTER_LINEAR,
+        BORDER_CONSTANT,
+        Scalar::default(),
+    )?;
+
+    Ok(unwarped)
 }
 
 pub fn get_game_roi(
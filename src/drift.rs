@@ -0,0 +1,82 @@
+use opencv::{core::*, imgproc::*, prelude::*};
+
+use crate::detection::Area;
+
+// frames between two checks
+const DRIFT_CHECK_PERIOD: usize = 30;
+// shift of the scene around the playing area above which the camera moved, in pixels
+const DRIFT_MAX_SHIFT: f64 = 3.0;
+// below this phase correlation peak the scene changed too much to be compared
+const DRIFT_MIN_RESPONSE: f64 = 0.1;
+// consecutive checks that must see a shift, a passer-by only disturbs one
+const DRIFT_CONFIRMATIONS: usize = 2;
+// margin kept around the playing area, where hands and projection spill over
+const DRIFT_AREA_MARGIN: i32 = 20;
+
+pub struct DriftMonitor {
+    // camera view around the playing area when the geometry was computed
+    reference: Mat,
+    mask: Mat,
+    window: Mat,
+    frames: usize,
+    confirmations: usize,
+}
+
+impl DriftMonitor {
+    pub fn new(frame: &Mat, area: &Area) -> opencv::Result<DriftMonitor> {
+        // the static scene outside the playing area is the reference: the
+        // projection and the players only change what's inside
+        let mut mask = Mat::zeros(frame.rows(), frame.cols(), CV_8UC1)?.to_mat()?;
+        mask.set_to(&Scalar::all(255.0), &no_array()?)?;
+        let inside = Rect::new(
+            area.origin.x as i32 - DRIFT_AREA_MARGIN,
+            area.origin.y as i32 - DRIFT_AREA_MARGIN,
+            area.size.width + 2 * DRIFT_AREA_MARGIN,
+            area.size.height + 2 * DRIFT_AREA_MARGIN,
+        );
+        rectangle(&mut mask, inside, Scalar::all(0.0), FILLED, LINE_8, 0)?;
+        let mut window = Mat::default()?;
+        create_hanning_window(&mut window, frame.size()?, CV_32F)?;
+
+        let mut monitor = DriftMonitor {
+            reference: Mat::default()?,
+            mask: mask,
+            window: window,
+            frames: 0,
+            confirmations: 0,
+        };
+        monitor.reference = monitor.surroundings(frame)?;
+
+        Ok(monitor)
+    }
+
+    pub fn check(&mut self, frame: &Mat) -> opencv::Result<bool> {
+        // periodically compares the surroundings of the playing area with the
+        // reference, true when the camera (or the surface) moved
+        self.frames += 1;
+        if self.frames % DRIFT_CHECK_PERIOD != 0 {
+            return Ok(false);
+        }
+        let current = self.surroundings(frame)?;
+        let mut response = 0.0;
+        let shift = phase_correlate(&self.reference, &current, &self.window, &mut response)?;
+        if response >= DRIFT_MIN_RESPONSE && shift.norm() > DRIFT_MAX_SHIFT {
+            self.confirmations += 1;
+        } else {
+            self.confirmations = 0;
+        }
+
+        Ok(self.confirmations >= DRIFT_CONFIRMATIONS)
+    }
+
+    fn surroundings(&self, frame: &Mat) -> opencv::Result<Mat> {
+        let mut gray = Mat::default()?;
+        cvt_color(frame, &mut gray, COLOR_BGR2GRAY, 0)?;
+        let mut masked = Mat::zeros(gray.rows(), gray.cols(), CV_8UC1)?.to_mat()?;
+        gray.copy_to_masked(&mut masked, &self.mask)?;
+        let mut float = Mat::default()?;
+        masked.convert_to(&mut float, CV_32F, 1.0, 0.0)?;
+
+        Ok(float)
+    }
+}
@@ -1,15 +1,18 @@
-use std::fmt;
+use std::{fmt, io};
 
 #[derive(Debug)]
 pub enum Error {
     Opencv(opencv::Error),
     DetectionError(ErrorKind),
+    Io(io::Error),
+    Config(String),
 }
 
 #[derive(Debug)]
 pub enum ErrorKind {
     AreaBiggerThanScreen,
     TooSmallArea,
+    FlashNotDetected,
 }
 
 impl ErrorKind {
@@ -19,6 +22,7 @@ impl ErrorKind {
                 "Detected playing area is larger than projected area. Re-run with '-f' (fullscreen)"
             }
             ErrorKind::TooSmallArea => "Can't detect playing area",
+            ErrorKind::FlashNotDetected => "Can't detect projected flash for latency measurement",
         }
     }
 }
@@ -29,11 +33,19 @@ impl From<opencv::Error> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Opencv(ref err) => err.fmt(f),
             Error::DetectionError(ref err) => write!(f, "Detection error: {:?}", err.as_str()),
+            Error::Io(ref err) => err.fmt(f),
+            Error::Config(ref err) => write!(f, "Config error: {}", err),
         }
     }
 }
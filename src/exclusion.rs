@@ -0,0 +1,99 @@
+use opencv::{core::*, highgui::*, imgproc::*, prelude::*, types::*, videoio::*};
+
+use crate::background::Background;
+use crate::config::ExclusionConfig;
+use crate::detection::{unwarp, Area, Detector};
+use crate::errors::Error;
+
+const STATIC_LEARNING_FRAMES: usize = 30;
+// margin around learnt static objects absorbing detection jitter, in pixels
+const STATIC_MARGIN: i32 = 7;
+
+pub fn detection_mask(
+    cam: &mut VideoCapture,
+    area: &Area,
+    detector: &mut Detector,
+    background: Option<&Background>,
+    config: &ExclusionConfig,
+) -> Result<Mat, Error> {
+    // mask of the unwarped area where shapes are detected: everything but the
+    // learnt static objects and the configured exclusion polygons
+    let mut excluded = Mat::zeros(area.size.height, area.size.width, CV_8UC1)?.to_mat()?;
+    if config.learn_static {
+        learn_static_objects(cam, area, detector, background, &mut excluded)?;
+    }
+    let mut polygons = VectorOfVectorOfPoint::new();
+    for polygon in config.polygons.iter() {
+        // polygon vertices are relative to the playing area (0.0 to 1.0)
+        polygons.push(VectorOfPoint::from_iter(polygon.iter().map(|p| {
+            Point::new(
+                (p[0] * area.size.width as f32) as i32,
+                (p[1] * area.size.height as f32) as i32,
+            )
+        })));
+    }
+    if !polygons.is_empty() {
+        fill_poly(
+            &mut excluded,
+            &polygons,
+            Scalar::all(255.0),
+            LINE_8,
+            0,
+            Point::new(0, 0),
+        )?;
+    }
+    let mut mask = Mat::default()?;
+    bitwise_not(&excluded, &mut mask, &no_array()?)?;
+
+    Ok(mask)
+}
+
+fn learn_static_objects(
+    cam: &mut VideoCapture,
+    area: &Area,
+    detector: &mut Detector,
+    background: Option<&Background>,
+    excluded: &mut Mat,
+) -> Result<(), Error> {
+    // every shape detected while the area is empty is a static object
+    let mut frame = Mat::default()?;
+    let no_mask = Mat::default()?;
+    for _i in 0..STATIC_LEARNING_FRAMES {
+        cam.read(&mut frame)?;
+        let mut unwarped = unwarp(&frame, area)?;
+        if let Some(b) = background {
+            b.remove_shadows(&mut unwarped)?;
+        }
+        let contours = detector.detect(&unwarped, &no_mask)?;
+        draw_contours(
+            excluded,
+            &contours,
+            -1,
+            Scalar::all(255.0),
+            FILLED,
+            LINE_8,
+            &no_array()?,
+            i32::MAX,
+            Point::new(0, 0),
+        )?;
+        wait_key(10)?;
+    }
+    let kernel = get_structuring_element(
+        MORPH_ELLIPSE,
+        Size::new(2 * STATIC_MARGIN + 1, 2 * STATIC_MARGIN + 1),
+        Point::new(-1, -1),
+    )?;
+    let mut dilated = Mat::default()?;
+    dilate(
+        &*excluded,
+        &mut dilated,
+        &kernel,
+        Point::new(-1, -1),
+        1,
+        BORDER_CONSTANT,
+        morphology_default_border_value()?,
+    )?;
+    *excluded = dilated;
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use opencv::{core::*, imgproc::*, prelude::*, types::*, video::*};
+
+// pixels per frame above which a pixel is moving
+const FLOW_MIN_MAGNITUDE: f64 = 1.5;
+const FLOW_MIN_AREA: f64 = 150.0;
+
+pub struct FlowDetector {
+    previous: Option<Mat>,
+    flow: Mat,
+}
+
+impl FlowDetector {
+    pub fn new() -> opencv::Result<FlowDetector> {
+        Ok(FlowDetector {
+            previous: None,
+            flow: Mat::default()?,
+        })
+    }
+
+    pub fn detect(&mut self, img: &Mat, mask: &Mat) -> opencv::Result<VectorOfVectorOfPoint> {
+        // regions of significant dense (farneback) optical flow since the previous frame
+        let mut contours = VectorOfVectorOfPoint::new();
+        let mut gray = Mat::default()?;
+        cvt_color(&img, &mut gray, COLOR_BGR2GRAY, 0)?;
+        let previous = match self.previous.replace(gray) {
+            Some(p) => p,
+            None => return Ok(contours),
+        };
+        let current = self.previous.as_ref().unwrap();
+        calc_optical_flow_farneback(&previous, current, &mut self.flow, 0.5, 3, 15, 3, 5, 1.2, 0)?;
+
+        let mut components = VectorOfMat::new();
+        split(&self.flow, &mut components)?;
+        let mut speed = Mat::default()?;
+        magnitude(&components.get(0)?, &components.get(1)?, &mut speed)?;
+        let mut moving = Mat::default()?;
+        in_range(
+            &speed,
+            &Scalar::all(FLOW_MIN_MAGNITUDE),
+            &Scalar::all(f64::MAX),
+            &mut moving,
+        )?;
+        let mut closed = Mat::default()?;
+        let kernel = get_structuring_element(MORPH_ELLIPSE, Size::new(7, 7), Point::new(-1, -1))?;
+        morphology_ex(
+            &moving,
+            &mut closed,
+            MORPH_CLOSE,
+            &kernel,
+            Point::new(-1, -1),
+            1,
+            BORDER_CONSTANT,
+            morphology_default_border_value()?,
+        )?;
+        if !mask.empty()? {
+            bitwise_and(&closed, mask, &mut moving, &no_array()?)?;
+            closed = moving;
+        }
+
+        let mut found = VectorOfVectorOfPoint::new();
+        find_contours(
+            &closed,
+            &mut found,
+            RETR_EXTERNAL,
+            CHAIN_APPROX_SIMPLE,
+            Point::new(0, 0),
+        )?;
+        for index in 0..found.len() {
+            let c = found.get(index)?;
+            if contour_area(&c, false)? >= FLOW_MIN_AREA {
+                contours.push(c);
+            }
+        }
+
+        Ok(contours)
+    }
+
+    pub fn motion(
+        &self,
+        contours: &VectorOfVectorOfPoint,
+        index: usize,
+    ) -> opencv::Result<Point2f> {
+        // mean optical flow inside a detected contour, in pixels per frame
+        let mut region = Mat::zeros(self.flow.rows(), self.flow.cols(), CV_8UC1)?.to_mat()?;
+        draw_contours(
+            &mut region,
+            contours,
+            index as i32,
+            Scalar::all(255.0),
+            FILLED,
+            LINE_8,
+            &no_array()?,
+            i32::MAX,
+            Point::new(0, 0),
+        )?;
+        let m = mean(&self.flow, &region)?;
+
+        Ok(Point2f::new(m[0] as f32, m[1] as f32))
+    }
+}
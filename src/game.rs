@@ -1,80 +1,704 @@
 use c2::{prelude::*, Circle, Poly};
-use opencv::{core::*, imgproc::*, types::*};
+use opencv::{core::*, imgproc::*};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
+use crate::ai::AiPaddle;
+use crate::breakout::Breakout;
+use crate::config::{AiConfig, Difficulty, MultiballConfig, PaddleRule, RulesConfig};
+use crate::detection::Shape;
 use crate::graphics::*;
 
-#[derive(Debug)]
+// share of the paddle speed along the contact normal given to the ball
+const MOMENTUM_TRANSFER: f32 = 0.8;
+// share of the paddle speed along the contact tangent given to the ball (spin)
+const SPIN_TRANSFER: f32 = 0.3;
+// physics time step, and longest time simulated at once, in seconds
+const PHYSICS_STEP: f32 = 1.0 / 120.0;
+const MAX_ELAPSED: f32 = 0.25;
+// longest ball move between two collision checks, in radii: thinner shapes
+// than this can't be skipped over
+const SWEEP_STEP: f32 = 0.5;
+// extra distance the ball is pushed out of a shape so the contact doesn't repeat
+const CONTACT_SLOP: f32 = 0.01;
+// seconds a scored point is shown before the next serve countdown
+const POINT_DELAY: f32 = 1.0;
+// seconds a difficulty change is shown
+const DIFFICULTY_DELAY: f32 = 2.0;
+
+struct Level {
+    // serve speed range along each axis, in field widths per second
+    serve_speed: (f32, f32),
+    // speed gained at each paddle hit, in field widths per second
+    speed_up: f32,
+    max_speed: f32,
+    // ball radius, in field widths
+    radius: f32,
+}
+
+fn level(difficulty: Difficulty) -> Level {
+    match difficulty {
+        Difficulty::Kids => Level {
+            serve_speed: (0.15, 0.22),
+            speed_up: 0.01,
+            max_speed: 0.4,
+            radius: 0.012,
+        },
+        Difficulty::Normal => Level {
+            serve_speed: (0.25, 0.42),
+            speed_up: 0.02,
+            max_speed: 0.75,
+            radius: 0.006,
+        },
+        Difficulty::Expert => Level {
+            serve_speed: (0.4, 0.55),
+            speed_up: 0.04,
+            max_speed: 1.1,
+            radius: 0.005,
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Player {
     Left,
     Right,
+    // four players mode only
+    Top,
+    Bottom,
+}
+
+impl Player {
+    pub const ALL: [Player; 4] = [Player::Left, Player::Right, Player::Top, Player::Bottom];
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn opponent(&self) -> Player {
+        match *self {
+            Player::Left => Player::Right,
+            Player::Right => Player::Left,
+            Player::Top => Player::Bottom,
+            Player::Bottom => Player::Top,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Player::Left => "left",
+            Player::Right => "right",
+            Player::Top => "top",
+            Player::Bottom => "bottom",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    // no match running, the ball bounces on every wall until someone hits it
+    Attract,
+    // seconds left before the serve
+    Countdown(f32),
+    Playing,
+    // seconds left showing the scored point
+    PointScored(f32),
+    // match winner (none in breakout mode), and seconds left before the rematch
+    GameOver(Option<Player>, f32),
+    Paused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Pause,
+    Resume,
+    ResetScore,
+    ResetBall,
+    ToggleSolo,
+    // detects the playing area again
+    Redetect,
+    Harder,
+    Easier,
+    // must be issued twice in a row to quit
+    Quit,
 }
 
 pub struct Game {
     size: Size,
-    ball: Ball,
+    // never empty, the first ball is the one served in single ball games
+    balls: Vec<Ball>,
     score: Score,
-    reset: bool,
+    rules: RulesConfig,
+    multiball: MultiballConfig,
+    // seconds of play and paddle hits since the last extra ball
+    spawn_timer: f32,
+    spawn_hits: u32,
+    // computer opponent, two players games only
+    ai: Option<AiPaddle>,
+    // bricks, lives and levels of the breakout mode
+    breakout: Option<Breakout>,
+    difficulty: Difficulty,
+    // seconds left showing a difficulty change
+    difficulty_shown: f32,
+    state: State,
+    // state resumed after a pause
+    paused_state: State,
+    // goal the first ball is served to, alternating between serves
+    next_serve: Player,
+    // commands handled by the application
+    confirming_quit: bool,
+    // the quit confirmation paused the game
+    quit_paused: bool,
+    quit: bool,
+    redetect: bool,
+    // simulated time not consumed by a physics step yet, in seconds
+    accumulator: f32,
     single_player: bool,
+    paddle_rule: PaddleRule,
     graphics: Graphics,
 }
 
 impl Game {
-    pub fn new(size: Size, single_player: bool) -> Game {
-        Game {
-            ball: Ball::new(size),
-            score: Score::new(),
-            reset: false,
+    pub fn new(
+        size: Size,
+        single_player: bool,
+        paddle_rule: PaddleRule,
+        rules: RulesConfig,
+        multiball: MultiballConfig,
+        ai: Option<AiConfig>,
+        breakout: Option<Breakout>,
+    ) -> Game {
+        // breakout is single player only
+        let single_player = single_player || breakout.is_some();
+        let mut game = Game {
+            balls: vec![Ball::new(size, rules.difficulty)],
+            score: Score::new(rules.lives),
+            multiball: multiball,
+            spawn_timer: 0.0,
+            spawn_hits: 0,
+            ai: ai.map(|a| AiPaddle::new(a, size)),
+            breakout: breakout,
+            difficulty: rules.difficulty,
+            difficulty_shown: 0.0,
+            rules: rules,
+            state: State::Attract,
+            paused_state: State::Attract,
+            next_serve: Player::Right,
+            confirming_quit: false,
+            quit_paused: false,
+            quit: false,
+            redetect: false,
+            accumulator: 0.0,
             size: size,
             single_player: single_player,
+            paddle_rule: paddle_rule,
             graphics: Graphics::init(size),
+        };
+        game.serve();
+        game
+    }
+    pub fn command(&mut self, command: Command) {
+        // any other command cancels a quit confirmation, the game resumes if
+        // the confirmation paused it
+        if command != Command::Quit && self.confirming_quit {
+            self.confirming_quit = false;
+            if self.quit_paused && self.state == State::Paused {
+                self.state = self.paused_state;
+            }
+        }
+        match command {
+            Command::Pause => {
+                if self.state != State::Paused {
+                    self.paused_state = self.state;
+                    self.state = State::Paused;
+                }
+            }
+            Command::Resume => {
+                if self.state == State::Paused {
+                    self.state = self.paused_state;
+                }
+            }
+            Command::ResetScore => self.start_match(),
+            Command::ResetBall => {
+                self.serve();
+                if self.state == State::Playing {
+                    self.state = State::Countdown(self.rules.countdown);
+                }
+            }
+            Command::ToggleSolo => {
+                // breakout is single player only
+                if self.breakout.is_none() {
+                    self.single_player = !self.single_player;
+                    self.start_match();
+                }
+            }
+            Command::Redetect => self.redetect = true,
+            Command::Harder => self.set_difficulty(self.difficulty.harder()),
+            Command::Easier => self.set_difficulty(self.difficulty.easier()),
+            Command::Quit => {
+                if self.confirming_quit {
+                    self.quit = true;
+                } else {
+                    self.quit_paused = self.state != State::Paused;
+                    self.command(Command::Pause);
+                    self.confirming_quit = true;
+                }
+            }
         }
     }
-    pub fn update(&mut self, shapes: &VectorOfRotatedRect) -> opencv::Result<()> {
-        if self.reset {
-            self.reset = false;
+    fn set_difficulty(&mut self, difficulty: Difficulty) {
+        // applied to the balls in play
+        self.difficulty = difficulty;
+        self.difficulty_shown = DIFFICULTY_DELAY;
+        for ball in self.balls.iter_mut() {
+            ball.set_level(self.size, difficulty);
+        }
+    }
+    pub fn update(&mut self, shapes: &[Shape], elapsed: f32) -> opencv::Result<()> {
+        // the time elapsed since the last update (in seconds) is simulated by
+        // fixed steps, whatever the pace of the loop
+        self.difficulty_shown = f32::max(0.0, self.difficulty_shown - elapsed);
+        match self.state {
+            State::Attract | State::Playing => {}
+            State::Countdown(left) => {
+                self.state = if left > elapsed {
+                    State::Countdown(left - elapsed)
+                } else {
+                    State::Playing
+                };
+            }
+            State::PointScored(left) => {
+                self.state = if left > elapsed {
+                    State::PointScored(left - elapsed)
+                } else {
+                    State::Countdown(self.rules.countdown)
+                };
+            }
+            State::GameOver(winner, left) => {
+                if left > elapsed {
+                    self.state = State::GameOver(winner, left - elapsed);
+                } else {
+                    self.start_match();
+                }
+            }
+            State::Paused => {}
+        }
+        let ai_playing = self.ai_playing();
+        if let Some(ref mut ai) = self.ai {
+            if ai_playing {
+                let balls: Vec<(Point2f, Point2f)> = self
+                    .balls
+                    .iter()
+                    .map(|b| (Point2f::new(b.x, b.y), Point2f::new(b.vel_x, b.vel_y)))
+                    .collect();
+                ai.update(&balls, elapsed);
+            }
+        }
+        if self.state != State::Attract && self.state != State::Playing {
+            self.accumulator = 0.0;
+            return Ok(());
+        }
+        if self.state == State::Playing && self.multiball.spawn_every > 0.0 {
+            self.spawn_timer += elapsed;
+            if self.spawn_timer >= self.multiball.spawn_every {
+                self.spawn_timer = 0.0;
+                self.spawn();
+            }
         }
 
-        self.ball.translate();
-        self.ball.wall_collision(self.size, self.single_player);
-        self.ball.shape_collision(shapes)?;
+        // shape velocities are measured in pixels per frame
+        let per_second = 1.0 / f32::max(elapsed, PHYSICS_STEP);
+        let mut collidable: Vec<(Shape, Poly)> = shapes
+            .iter()
+            .filter(|s| self.can_hit(s))
+            .map(|s| {
+                let shape = Shape {
+                    velocity: Point2f::new(s.velocity.x * per_second, s.velocity.y * per_second),
+                    ..*s
+                };
+                (shape, shape_poly(&shape))
+            })
+            .collect();
+        if let Some(ref ai) = self.ai {
+            if ai_playing && self.state == State::Playing {
+                // velocity already in pixels per second
+                let shape = ai.shape();
+                collidable.push((shape, shape_poly(&shape)));
+            }
+        }
+        self.accumulator = f32::min(self.accumulator + elapsed, MAX_ELAPSED);
+        while self.accumulator >= PHYSICS_STEP {
+            self.accumulator -= PHYSICS_STEP;
+            self.step(&collidable);
+            if self.state != State::Attract && self.state != State::Playing {
+                self.accumulator = 0.0;
+            }
+        }
 
-        if self.ball.x < 0 {
-            self.score.add_right();
-            self.reset = true;
+        Ok(())
+    }
+
+    fn step(&mut self, shapes: &[(Shape, Poly)]) {
+        // each ball collides with the shapes, the walls and the other balls
+        let attract = self.state == State::Attract;
+        let goals = self.goals();
+        let walls = [!goals[0], !goals[1], !goals[2], !goals[3]];
+        let bricks: &[(Shape, Poly)] = match self.breakout {
+            Some(ref b) => b.bricks(),
+            None => &[],
+        };
+        let mut hits = Vec::new();
+        let mut brick_hits = Vec::new();
+        for ball in self.balls.iter_mut() {
+            // the shapes are the first group, the bricks the second
+            let hit = ball.sweep(PHYSICS_STEP, &[shapes, bricks]);
+            ball.wall_collision(self.size, walls);
+            if let Some((group, index)) = hit {
+                if !attract {
+                    ball.speed_up();
+                }
+                if group == 0 {
+                    hits.push(shapes[index].0);
+                } else {
+                    brick_hits.push(index);
+                }
+            }
+        }
+        ball_collisions(&mut self.balls);
+        if attract {
+            if !hits.is_empty() {
+                // the first hit (of a shape) starts a match
+                self.start_match();
+            }
+            return;
+        }
+        if let Some(ref mut breakout) = self.breakout {
+            // a brick hit by several balls at once loses a single hit, the last
+            // bricks are hit first as clearing one shifts the following ones
+            brick_hits.sort();
+            brick_hits.dedup();
+            for index in brick_hits.iter().rev() {
+                breakout.hit(*index);
+            }
+        }
+        for hit in hits.iter() {
+            if let Some(owner) = hit.owner {
+                self.score.add_hit(owner);
+            }
+            self.spawn_hits += 1;
+            if self.multiball.spawn_hits > 0 && self.spawn_hits >= self.multiball.spawn_hits {
+                self.spawn_hits = 0;
+                self.spawn();
+            }
+        }
+        if self.breakout.as_ref().map_or(false, |b| b.cleared()) {
+            self.next_level();
+            return;
+        }
+
+        // every ball entering a goal scores
+        let mut i = 0;
+        while i < self.balls.len() {
+            match self.balls[i].out(self.size) {
+                Some(side) if goals[side.index()] => {
+                    self.balls.remove(i);
+                    if self.breakout.is_some() {
+                        self.lose_ball();
+                    } else if self.four_players() {
+                        self.lose_life(side);
+                    } else {
+                        self.point(side.opponent());
+                    }
+                    if self.state != State::Playing {
+                        return;
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    fn ai_playing(&self) -> bool {
+        // the computer plays matches against a single player
+        match self.state {
+            State::Countdown(_) | State::Playing | State::PointScored(_) => {
+                self.ai.is_some()
+                    && self.breakout.is_none()
+                    && !self.single_player
+                    && !self.four_players()
+            }
+            _ => false,
+        }
+    }
+
+    fn four_players(&self) -> bool {
+        self.rules.players == 4 && !self.single_player && self.breakout.is_none()
+    }
+
+    fn goals(&self) -> [bool; 4] {
+        // field edges open as goals, indexed by their player: the other edges
+        // are walls
+        if self.state == State::Attract {
+            return [false; 4];
+        }
+        if self.four_players() {
+            let lives = self.score.lives;
+            return [lives[0] > 0, lives[1] > 0, lives[2] > 0, lives[3] > 0];
+        }
+        if self.breakout.is_some() {
+            // the player defends the left edge, facing the bricks
+            return [true, false, false, false];
+        }
+        [true, !self.single_player, false, false]
+    }
+
+    fn lose_ball(&mut self) {
+        // breakout mode: a life is lost once every ball has left the field
+        if !self.balls.is_empty() {
+            return;
+        }
+        self.serve();
+        let alive = match self.breakout {
+            Some(ref mut b) => b.lose_life(),
+            None => true,
+        };
+        self.state = if alive {
+            State::PointScored(POINT_DELAY)
+        } else {
+            State::GameOver(None, self.rules.game_over)
+        };
+    }
+
+    fn next_level(&mut self) {
+        // breakout mode: the cleared level is followed by the next one, the
+        // game ends after the last
+        self.serve();
+        let next = match self.breakout {
+            Some(ref mut b) => b.next_level(),
+            None => true,
+        };
+        self.state = if next {
+            State::PointScored(POINT_DELAY)
+        } else {
+            State::GameOver(None, self.rules.game_over)
+        };
+    }
+
+    fn lose_life(&mut self, player: Player) {
+        // eliminated players' goals become walls, the last player left wins
+        self.score.lose_life(player);
+        let alive = self.score.alive();
+        if alive.len() <= 1 {
+            self.serve();
+            let winner = alive.first().cloned().unwrap_or(player);
+            self.state = State::GameOver(Some(winner), self.rules.game_over);
+            return;
+        }
+        if self.balls.is_empty() {
+            self.serve();
+            self.state = State::PointScored(POINT_DELAY);
+        }
+    }
+
+    fn start_match(&mut self) {
+        self.score = Score::new(self.rules.lives);
+        if let Some(ref mut breakout) = self.breakout {
+            breakout.start();
         }
+        self.serve();
+        self.state = State::Countdown(self.rules.countdown);
+    }
+
+    fn serve(&mut self) {
+        // the first ball alternates sides, the other served balls are sent
+        // to alternate sides too and spread vertically
+        let count = u32::max(1, self.multiball.balls) as usize;
+        self.balls.truncate(1);
+        if self.balls.is_empty() {
+            self.balls.push(Ball::new(self.size, self.difficulty));
+        }
+        self.balls[0].reset(self.size);
+        self.balls[0].aim(self.next_serve);
+        self.next_serve = match self.next_serve {
+            Player::Right => Player::Left,
+            _ => Player::Right,
+        };
+        let side = self.balls[0].vel_x.signum();
+        for i in 1..count {
+            let mut ball = Ball::new(self.size, self.difficulty);
+            ball.vel_x = ball.vel_x.abs() * if i % 2 == 0 { side } else { -side };
+            self.balls.push(ball);
+        }
+        for (i, ball) in self.balls.iter_mut().enumerate() {
+            ball.y = self.size.height as f32 * (i + 1) as f32 / (count + 1) as f32;
+            // breakout balls are served towards the bricks
+            if self.breakout.is_some() {
+                ball.vel_x = ball.vel_x.abs();
+            }
+        }
+        if self.four_players() {
+            // from the center, each ball heads to a live player's goal in turn,
+            // starting from a random one
+            let mut alive = self.score.alive();
+            if alive.is_empty() {
+                alive = Player::ALL.to_vec();
+            }
+            let first = rand::thread_rng().gen_range(0..alive.len());
+            let center = Point2f::new(self.size.width as f32 / 2.0, self.size.height as f32 / 2.0);
+            for (i, ball) in self.balls.iter_mut().enumerate() {
+                ball.aim(alive[(first + i) % alive.len()]);
+                // balls heading to the same goal are spaced out
+                let speed = (ball.vel_x * ball.vel_x + ball.vel_y * ball.vel_y).sqrt();
+                let offset = 3.0 * ball.radius * (i / alive.len()) as f32;
+                ball.x = center.x + ball.vel_x / speed * offset;
+                ball.y = center.y + ball.vel_y / speed * offset;
+            }
+        }
+        self.spawn_timer = 0.0;
+        self.spawn_hits = 0;
+    }
+
+    fn spawn(&mut self) {
+        // extra ball from the center, to a random side (or live player)
+        if self.balls.len() >= self.multiball.max_balls as usize {
+            return;
+        }
+        let mut ball = Ball::new(self.size, self.difficulty);
+        let alive = self.score.alive();
+        if self.four_players() && !alive.is_empty() {
+            // to a live player's goal
+            ball.aim(alive[rand::thread_rng().gen_range(0..alive.len())]);
+        } else if rand::random() {
+            ball.vel_x *= -1.0;
+        }
+        self.balls.push(ball);
+    }
+
+    fn point(&mut self, player: Player) {
+        // a set is won with the target points and enough lead, the match with
+        // most of the sets (solo games never end), the balls are served again
+        // once all have left the field or when a set ends
+        self.score.add_point(player);
         if !self.single_player {
-            if self.ball.x + self.ball.radius > self.size.width {
-                self.score.add_left();
-                self.reset = true;
+            let (points, other) = self.score.points(player);
+            if points >= self.rules.target && points >= other + self.rules.win_by {
+                // the last set's points stay shown until the rematch
+                self.score.add_set(player);
+                self.serve();
+                self.state = if self.score.sets(player) > self.rules.sets / 2 {
+                    State::GameOver(Some(player), self.rules.game_over)
+                } else {
+                    self.score.new_set();
+                    State::PointScored(POINT_DELAY)
+                };
+                return;
             }
         }
-        if self.reset {
-            self.ball.reset(self.size);
+        if self.balls.is_empty() {
+            self.serve();
+            self.state = State::PointScored(POINT_DELAY);
         }
+    }
 
-        Ok(())
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == State::Paused
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    pub fn take_redetect(&mut self) -> bool {
+        // true once per re-detection command
+        let redetect = self.redetect;
+        self.redetect = false;
+        redetect
+    }
+
+    pub fn ball_center(&self) -> Point {
+        // the first ball only, the one followed by the projection verification
+        self.balls[0].get_center()
+    }
+
+    pub fn ball_radius(&self) -> i32 {
+        self.balls[0].radius.round() as i32
+    }
+
+    fn can_hit(&self, shape: &Shape) -> bool {
+        // shapes tagged with a player's marker may be restricted to their half
+        if self.single_player || self.paddle_rule == PaddleRule::Anywhere {
+            return true;
+        }
+        let half_width = self.size.width as f32 / 2.0;
+        let half_height = self.size.height as f32 / 2.0;
+        match shape.owner {
+            Some(Player::Left) => shape.center.x <= half_width,
+            Some(Player::Right) => shape.center.x >= half_width,
+            Some(Player::Top) => shape.center.y <= half_height,
+            Some(Player::Bottom) => shape.center.y >= half_height,
+            None => true,
+        }
     }
 
     pub fn draw(&self, img: &mut Mat) -> opencv::Result<()> {
         // reset solid background
         img.set_to(&self.graphics.bg_color, &no_array()?)?;
 
-        // draw ball
-        circle(
-            img,
-            self.ball.get_center(),
-            self.ball.radius,
-            self.graphics.obj_color,
-            -1,
-            LINE_8,
-            0,
-        )?;
-        // draw score
-        if !self.single_player {
+        // draw the computer paddle
+        if let Some(ref ai) = self.ai {
+            if self.ai_playing() {
+                rectangle(
+                    img,
+                    ai.shape().bounding_rect(),
+                    self.graphics.obj_color,
+                    -1,
+                    LINE_8,
+                    0,
+                )?;
+            }
+        }
+        // draw the bricks, with the hits left by the sturdier ones
+        if let Some(ref breakout) = self.breakout {
+            for ((brick, _), hits) in breakout.bricks().iter().zip(breakout.brick_hits()) {
+                let rect = brick.bounding_rect();
+                rectangle(img, rect, self.graphics.obj_color, -1, LINE_8, 0)?;
+                if *hits > 1 {
+                    put_text(
+                        img,
+                        &hits.to_string(),
+                        Point::new(rect.x + rect.width / 3, rect.y + 2 * rect.height / 3),
+                        self.graphics.font,
+                        0.8,
+                        self.graphics.bg_color,
+                        2,
+                        LINE_8,
+                        false,
+                    )?;
+                }
+            }
+        }
+        // draw balls
+        for ball in self.balls.iter() {
+            circle(
+                img,
+                ball.get_center(),
+                ball.radius.round() as i32,
+                self.graphics.obj_color,
+                -1,
+                LINE_8,
+                0,
+            )?;
+        }
+        // draw score, or the lives of each player along their goal
+        if let Some(ref breakout) = self.breakout {
             put_text(
                 img,
-                &self.score.left.to_string(),
+                &breakout.score().to_string(),
                 self.graphics.score_pos_left,
                 self.graphics.font,
                 2.0,
@@ -83,139 +707,412 @@ impl Game {
                 LINE_8,
                 false,
             )?;
+            put_text(
+                img,
+                &format!("lives {}", breakout.lives()),
+                self.graphics.sets_pos_left,
+                self.graphics.font,
+                0.8,
+                self.graphics.score_color,
+                1,
+                LINE_8,
+                false,
+            )?;
+        } else if self.four_players() {
+            for player in Player::ALL.iter() {
+                let lives = self.score.lives[player.index()];
+                put_text(
+                    img,
+                    &if lives > 0 {
+                        lives.to_string()
+                    } else {
+                        "-".to_string()
+                    },
+                    self.graphics.lives_pos[player.index()],
+                    self.graphics.font,
+                    2.0,
+                    self.graphics.score_color,
+                    2,
+                    LINE_8,
+                    false,
+                )?;
+            }
+        } else {
+            if !self.single_player {
+                put_text(
+                    img,
+                    &self.score.points(Player::Left).0.to_string(),
+                    self.graphics.score_pos_left,
+                    self.graphics.font,
+                    2.0,
+                    self.graphics.score_color,
+                    2,
+                    LINE_8,
+                    false,
+                )?;
+            }
+            put_text(
+                img,
+                &self.score.points(Player::Right).0.to_string(),
+                self.graphics.score_pos_right,
+                self.graphics.font,
+                2.0,
+                self.graphics.score_color,
+                2,
+                LINE_8,
+                false,
+            )?;
+        }
+        if self.rules.sets > 1
+            && self.breakout.is_none()
+            && !self.single_player
+            && !self.four_players()
+        {
+            for (sets, pos) in [
+                (self.score.sets(Player::Left), self.graphics.sets_pos_left),
+                (self.score.sets(Player::Right), self.graphics.sets_pos_right),
+            ]
+            .iter()
+            {
+                put_text(
+                    img,
+                    &format!("sets {}", sets),
+                    *pos,
+                    self.graphics.font,
+                    0.8,
+                    self.graphics.score_color,
+                    1,
+                    LINE_8,
+                    false,
+                )?;
+            }
+        }
+        let message = match self.state {
+            _ if self.confirming_quit => Some("PRESS Q AGAIN TO QUIT".to_string()),
+            _ if self.difficulty_shown > 0.0 => Some(self.difficulty.name().to_uppercase()),
+            State::Attract => Some("HIT THE BALL".to_string()),
+            State::Countdown(left) => Some((left.ceil() as i32).to_string()),
+            State::GameOver(Some(winner), _) => {
+                Some(format!("{} WINS", winner.name().to_uppercase()))
+            }
+            State::GameOver(None, _) => match self.breakout {
+                Some(ref b) if b.cleared() => Some("YOU WIN".to_string()),
+                _ => Some("GAME OVER".to_string()),
+            },
+            State::PointScored(_) if self.breakout.is_some() => self
+                .breakout
+                .as_ref()
+                .map(|b| format!("LEVEL {}", b.level())),
+            State::Paused => Some("PAUSE".to_string()),
+            State::Playing | State::PointScored(_) => None,
+        };
+        if let Some(message) = message {
+            put_text(
+                img,
+                &message,
+                self.graphics.center_pos,
+                self.graphics.font,
+                2.0,
+                self.graphics.score_color,
+                2,
+                LINE_8,
+                false,
+            )?;
+        }
+        // draw hits credited through player markers
+        let hits = self.score.hits;
+        if !self.four_players() && hits[0] + hits[1] > 0 {
+            for (hits, pos) in [
+                (hits[0], self.graphics.hits_pos_left),
+                (hits[1], self.graphics.hits_pos_right),
+            ]
+            .iter()
+            {
+                put_text(
+                    img,
+                    &format!("hits {}", hits),
+                    *pos,
+                    self.graphics.font,
+                    0.8,
+                    self.graphics.score_color,
+                    1,
+                    LINE_8,
+                    false,
+                )?;
+            }
         }
-        put_text(
-            img,
-            &self.score.right.to_string(),
-            self.graphics.score_pos_right,
-            self.graphics.font,
-            2.0,
-            self.graphics.score_color,
-            2,
-            LINE_8,
-            false,
-        )?;
 
         Ok(())
     }
 }
 
 struct Ball {
-    x: i32,
-    y: i32,
-    vel_x: i32,
-    vel_y: i32,
-    radius: i32,
-    starting_side: Player,
+    // position in pixels, velocity in pixels per second
+    x: f32,
+    y: f32,
+    vel_x: f32,
+    vel_y: f32,
+    radius: f32,
+    serve_speed: (f32, f32),
+    speed_up: f32,
+    max_speed: f32,
 }
 
 impl Ball {
-    fn new(screen_size: Size) -> Ball {
-        let mut rng = rand::thread_rng();
-        Ball {
-            x: screen_size.width / 2,
-            y: screen_size.height / 2,
-            vel_x: rng.gen_range(20..30),
-            vel_y: rng.gen_range(20..30),
-            radius: 10,
-            starting_side: Player::Right,
+    fn new(screen_size: Size, difficulty: Difficulty) -> Ball {
+        let mut ball = Ball {
+            x: 0.0,
+            y: 0.0,
+            vel_x: 0.0,
+            vel_y: 0.0,
+            radius: 0.0,
+            serve_speed: (0.0, 0.0),
+            speed_up: 0.0,
+            max_speed: 0.0,
+        };
+        ball.set_level(screen_size, difficulty);
+        ball.reset(screen_size);
+        ball
+    }
+    pub fn set_level(&mut self, screen: Size, difficulty: Difficulty) {
+        // the level is relative to the field width, the current speed is kept
+        // within the new maximum
+        let level = level(difficulty);
+        let width = screen.width as f32;
+        self.radius = level.radius * width;
+        self.serve_speed = (level.serve_speed.0 * width, level.serve_speed.1 * width);
+        self.speed_up = level.speed_up * width;
+        self.max_speed = level.max_speed * width;
+        self.limit_speed();
+    }
+    pub fn speed_up(&mut self) {
+        let speed = (self.vel_x * self.vel_x + self.vel_y * self.vel_y).sqrt();
+        if speed > 0.0 {
+            self.vel_x *= (speed + self.speed_up) / speed;
+            self.vel_y *= (speed + self.speed_up) / speed;
+        }
+        self.limit_speed();
+    }
+    fn limit_speed(&mut self) {
+        let speed = (self.vel_x * self.vel_x + self.vel_y * self.vel_y).sqrt();
+        if speed > self.max_speed {
+            self.vel_x *= self.max_speed / speed;
+            self.vel_y *= self.max_speed / speed;
         }
     }
-    pub fn translate(&mut self) {
-        self.x += self.vel_x;
-        self.y += self.vel_y;
+    pub fn translate(&mut self, dt: f32) {
+        self.x += self.vel_x * dt;
+        self.y += self.vel_y * dt;
     }
     pub fn reset(&mut self, screen: Size) {
         let mut rng = rand::thread_rng();
-        self.x = screen.width / 2;
-        self.y = screen.height / 2;
-        self.vel_x = rng.gen_range(15..25);
-        self.vel_y = rng.gen_range(15..25);
-        match self.starting_side {
-            Player::Right => {
-                self.vel_x *= -1;
-                self.starting_side = Player::Left;
-            }
-            Player::Left => {
-                self.starting_side = Player::Right;
+        self.x = screen.width as f32 / 2.0;
+        self.y = screen.height as f32 / 2.0;
+        self.vel_x = rng.gen_range(self.serve_speed.0..self.serve_speed.1);
+        self.vel_y = rng.gen_range(self.serve_speed.0..self.serve_speed.1);
+        if rand::random() {
+            self.vel_y *= -1.0;
+        }
+    }
+    fn aim(&mut self, goal: Player) {
+        // the serve speed along the field axis leads to the goal, the other
+        // component is kept
+        let (along, across) = (self.vel_x.abs(), self.vel_y);
+        let (vel_x, vel_y) = match goal {
+            Player::Left => (-along, across),
+            Player::Right => (along, across),
+            Player::Top => (across, -along),
+            Player::Bottom => (across, along),
+        };
+        self.vel_x = vel_x;
+        self.vel_y = vel_y;
+    }
+    pub fn wall_collision(&mut self, screen: Size, walls: [bool; 4]) {
+        // bounces on the solid edges, indexed by player
+        if walls[Player::Top.index()] && self.y - self.radius <= 0.0 {
+            self.vel_y = self.vel_y.abs();
+            self.y = self.radius;
+        }
+        if walls[Player::Bottom.index()] && self.y + self.radius >= screen.height as f32 {
+            self.vel_y = -self.vel_y.abs();
+            self.y = screen.height as f32 - self.radius;
+        }
+        if walls[Player::Left.index()] && self.x - self.radius <= 0.0 {
+            self.vel_x = self.vel_x.abs();
+            self.x = self.radius;
+        }
+        if walls[Player::Right.index()] && self.x + self.radius >= screen.width as f32 {
+            self.vel_x = -self.vel_x.abs();
+            self.x = screen.width as f32 - self.radius;
+        }
+    }
+    pub fn out(&self, screen: Size) -> Option<Player> {
+        // edge of the field the ball went through
+        if self.x < 0.0 {
+            Some(Player::Left)
+        } else if self.x + self.radius > screen.width as f32 {
+            Some(Player::Right)
+        } else if self.y < 0.0 {
+            Some(Player::Top)
+        } else if self.y + self.radius > screen.height as f32 {
+            Some(Player::Bottom)
+        } else {
+            None
+        }
+    }
+    pub fn sweep(&mut self, dt: f32, shapes: &[&[(Shape, Poly)]]) -> Option<(usize, usize)> {
+        // moves the ball by substeps shorter than its radius, checking collisions
+        // after each so that it can't tunnel through thin shapes, returns the last
+        // shape hit (group and index in the group)
+        let speed = (self.vel_x * self.vel_x + self.vel_y * self.vel_y).sqrt();
+        let substeps = f32::max(1.0, (speed * dt / (SWEEP_STEP * self.radius)).ceil()) as usize;
+        let mut hit = None;
+        for _i in 0..substeps {
+            self.translate(dt / substeps as f32);
+            if let Some(h) = self.shape_collision(shapes) {
+                hit = Some(h);
             }
         }
-        if rand::random() {
-            self.vel_y *= 1;
-        }
-    }
-    pub fn wall_collision(&mut self, screen: Size, single_player: bool) {
-        if self.y - self.radius <= 0 {
-            self.vel_y *= -1;
-            self.y += 1;
-        }
-        if self.y + self.radius >= screen.height {
-            self.vel_y *= -1;
-            self.y -= 1;
-        }
-        if single_player {
-            if self.x + self.radius >= screen.width {
-                self.vel_x *= -1;
-                self.x -= 1;
-            }
-        }
-    }
-    pub fn shape_collision(&mut self, shapes: &VectorOfRotatedRect) -> opencv::Result<()> {
-        let circle = Circle::new([self.x as f32, self.y as f32], self.radius as f32);
-        for index in 0..shapes.len() {
-            let shape = shapes.get(index)?;
-            let mut vertices: [Point2f; 4] = [
-                Point2f::new(0.0, 0.0),
-                Point2f::new(0.0, 0.0),
-                Point2f::new(0.0, 0.0),
-                Point2f::new(0.0, 0.0),
-            ];
-            shape.points(&mut vertices)?;
-            let poly = Poly::from_slice(&[
-                [vertices[0].x, vertices[0].y],
-                [vertices[1].x, vertices[1].y],
-                [vertices[2].x, vertices[2].y],
-                [vertices[3].x, vertices[3].y],
-            ]);
-            let collided = circle.collides_with(&poly);
-            if collided {
-                let manifold = circle.manifold(&poly);
-                let depth = manifold.depths()[0].round().abs() as i32;
-                self.x -= depth * self.vel_x.signum();
-                self.y -= depth * self.vel_y.signum();
-                let normal = manifold.normal();
-                let x = normal.x().round().abs() as i32;
-                let y = normal.y().round().abs() as i32;
-                if x == 0 && y == 1 {
-                    self.vel_y *= -1;
-                } else if x == 1 && y == 0 {
-                    self.vel_x *= -1;
-                } else {
-                    self.vel_x *= -1;
-                    self.vel_y *= -1;
-                }
+        hit
+    }
+    pub fn shape_collision(&mut self, shapes: &[&[(Shape, Poly)]]) -> Option<(usize, usize)> {
+        // every contact is resolved: the ball is pushed out of the shape along the
+        // contact normal and its velocity reflected about it if moving inwards,
+        // the paddle motion is transferred through the deepest contact, whose
+        // group and index are returned
+        let mut deepest: Option<((usize, usize), Point2f, f32, f32, f32)> = None;
+        let all = shapes
+            .iter()
+            .enumerate()
+            .flat_map(|(g, s)| s.iter().enumerate().map(move |(i, c)| ((g, i), c)));
+        for (id, (shape, poly)) in all {
+            let circle = Circle::new([self.x, self.y], self.radius);
+            if !circle.collides_with(poly) {
+                continue;
+            }
+            let manifold = circle.manifold(poly);
+            let depth = manifold.depths()[0].abs();
+            // manifold normal points from the ball to the shape
+            let normal = manifold.normal();
+            let (nx, ny) = (-normal.x(), -normal.y());
+            self.x += (depth + CONTACT_SLOP) * nx;
+            self.y += (depth + CONTACT_SLOP) * ny;
+            let inwards = self.vel_x * nx + self.vel_y * ny;
+            if inwards < 0.0 {
+                self.vel_x -= 2.0 * inwards * nx;
+                self.vel_y -= 2.0 * inwards * ny;
+            }
+            match deepest {
+                Some((_, _, d, _, _)) if d >= depth => {}
+                _ => deepest = Some((id, shape.velocity, depth, nx, ny)),
             }
         }
-        Ok(())
+        let (id, velocity, _, nx, ny) = deepest?;
+        self.transfer_momentum(nx, ny, velocity);
+        Some(id)
+    }
+    fn transfer_momentum(&mut self, nx: f32, ny: f32, paddle_vel: Point2f) {
+        // adds the paddle motion to the bounced ball: the part pushing along the
+        // contact normal (only when moving towards the ball) and some tangential spin
+        let push = f32::max(0.0, paddle_vel.x * nx + paddle_vel.y * ny);
+        let spin = paddle_vel.x * -ny + paddle_vel.y * nx;
+        self.vel_x += MOMENTUM_TRANSFER * push * nx + SPIN_TRANSFER * spin * -ny;
+        self.vel_y += MOMENTUM_TRANSFER * push * ny + SPIN_TRANSFER * spin * nx;
+        self.limit_speed();
     }
     pub fn get_center(&self) -> Point {
-        Point::new(self.x, self.y)
+        Point::new(self.x.round() as i32, self.y.round() as i32)
+    }
+}
+
+fn ball_collisions(balls: &mut [Ball]) {
+    // elastic collisions between balls of the same mass: the velocities are
+    // exchanged along the line between the centers, the balls pushed apart
+    for i in 0..balls.len() {
+        for j in i + 1..balls.len() {
+            let (head, tail) = balls.split_at_mut(j);
+            let (a, b) = (&mut head[i], &mut tail[0]);
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            let distance = (dx * dx + dy * dy).sqrt();
+            let overlap = a.radius + b.radius - distance;
+            if overlap <= 0.0 || distance == 0.0 {
+                continue;
+            }
+            let (nx, ny) = (dx / distance, dy / distance);
+            a.x -= nx * (overlap / 2.0 + CONTACT_SLOP);
+            a.y -= ny * (overlap / 2.0 + CONTACT_SLOP);
+            b.x += nx * (overlap / 2.0 + CONTACT_SLOP);
+            b.y += ny * (overlap / 2.0 + CONTACT_SLOP);
+            let approach = (a.vel_x - b.vel_x) * nx + (a.vel_y - b.vel_y) * ny;
+            if approach > 0.0 {
+                a.vel_x -= approach * nx;
+                a.vel_y -= approach * ny;
+                b.vel_x += approach * nx;
+                b.vel_y += approach * ny;
+            }
+        }
     }
 }
 
+pub fn shape_poly(shape: &Shape) -> Poly {
+    let vertices = shape.vertices();
+    Poly::from_slice(&[
+        [vertices[0].x, vertices[0].y],
+        [vertices[1].x, vertices[1].y],
+        [vertices[2].x, vertices[2].y],
+        [vertices[3].x, vertices[3].y],
+    ])
+}
+
 struct Score {
-    left: i32,
-    right: i32,
+    // indexed by player: points of the current set, sets won, hits credited
+    // through markers and lives left (four players)
+    points: [u32; 4],
+    sets: [u32; 4],
+    hits: [i32; 4],
+    lives: [u32; 4],
 }
 
 impl Score {
-    fn new() -> Score {
-        Score { left: 0, right: 0 }
+    fn new(lives: u32) -> Score {
+        Score {
+            points: [0; 4],
+            sets: [0; 4],
+            hits: [0; 4],
+            lives: [lives; 4],
+        }
+    }
+    fn add_hit(&mut self, player: Player) {
+        self.hits[player.index()] += 1;
+    }
+    fn add_point(&mut self, player: Player) {
+        self.points[player.index()] += 1;
+    }
+    fn points(&self, player: Player) -> (u32, u32) {
+        // player's points, then the opponent's
+        (
+            self.points[player.index()],
+            self.points[player.opponent().index()],
+        )
+    }
+    fn add_set(&mut self, player: Player) {
+        self.sets[player.index()] += 1;
+    }
+    fn new_set(&mut self) {
+        self.points = [0; 4];
+    }
+    fn sets(&self, player: Player) -> u32 {
+        self.sets[player.index()]
     }
-    fn add_left(&mut self) {
-        self.left += 1
+    fn lose_life(&mut self, player: Player) {
+        let lives = &mut self.lives[player.index()];
+        *lives = lives.saturating_sub(1);
     }
-    fn add_right(&mut self) {
-        self.right += 1
+    fn alive(&self) -> Vec<Player> {
+        Player::ALL
+            .iter()
+            .filter(|p| self.lives[p.index()] > 0)
+            .cloned()
+            .collect()
     }
 }
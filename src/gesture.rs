@@ -0,0 +1,127 @@
+use opencv::{core::*, imgproc::*, prelude::*, types::*};
+use std::time::{Duration, Instant};
+
+use crate::game::Command;
+
+const GESTURE_HOLD: Duration = Duration::from_secs(2);
+const GESTURE_MIN_AREA: f64 = 1500.0;
+// defects deeper than this share of the hand height separate two fingers
+const FINGER_MIN_DEPTH: f32 = 0.15;
+// hull area share above which a hand without defects is a fist
+const FIST_SOLIDITY: f64 = 0.85;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    OpenPalm,
+    Fist,
+    Fingers(u8),
+}
+
+impl Gesture {
+    fn command(&self) -> Option<Command> {
+        match *self {
+            Gesture::OpenPalm => Some(Command::Pause),
+            Gesture::Fist => Some(Command::Resume),
+            Gesture::Fingers(2) => Some(Command::ResetScore),
+            Gesture::Fingers(3) => Some(Command::ToggleSolo),
+            Gesture::Fingers(_) => None,
+        }
+    }
+}
+
+pub struct GestureRecognizer {
+    current: Option<Gesture>,
+    since: Instant,
+    fired: bool,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> GestureRecognizer {
+        GestureRecognizer {
+            current: None,
+            since: Instant::now(),
+            fired: false,
+        }
+    }
+
+    pub fn update(&mut self, contours: &VectorOfVectorOfPoint) -> opencv::Result<Option<Command>> {
+        // the gesture of the largest hand must be held to issue its command once
+        let mut largest: Option<(VectorOfPoint, f64)> = None;
+        for index in 0..contours.len() {
+            let c = contours.get(index)?;
+            let area = contour_area(&c, false)?;
+            match largest {
+                Some((_, a)) if a >= area => {}
+                _ => largest = Some((c, area)),
+            }
+        }
+        let gesture = match largest {
+            Some((c, area)) if area >= GESTURE_MIN_AREA => recognize(&c, area)?,
+            _ => None,
+        };
+
+        if gesture != self.current {
+            self.current = gesture;
+            self.since = Instant::now();
+            self.fired = false;
+            return Ok(None);
+        }
+        if self.fired || self.since.elapsed() < GESTURE_HOLD {
+            return Ok(None);
+        }
+        self.fired = true;
+
+        Ok(gesture.and_then(|g| g.command()))
+    }
+}
+
+pub fn recognize(contour: &VectorOfPoint, area: f64) -> opencv::Result<Option<Gesture>> {
+    // counts fingers from the deep convexity defects between them
+    let mut hull = VectorOfi32::new();
+    convex_hull(contour, &mut hull, false, false)?;
+    if hull.len() < 3 {
+        return Ok(None);
+    }
+    let mut defects = VectorOfVec4i::new();
+    if convexity_defects(contour, &hull, &mut defects).is_err() {
+        // self-intersecting contours have no usable hull
+        return Ok(None);
+    }
+    let height = bounding_rect(contour)?.height as f32;
+    let mut gaps = 0;
+    for index in 0..defects.len() {
+        let d = defects.get(index)?;
+        let depth = d[3] as f32 / 256.0;
+        if depth < FINGER_MIN_DEPTH * height {
+            continue;
+        }
+        // the angle between two fingers is sharp
+        let start = contour.get(d[0] as usize)?;
+        let end = contour.get(d[1] as usize)?;
+        let far = contour.get(d[2] as usize)?;
+        let a = (start - far).norm();
+        let b = (end - far).norm();
+        let c = (start - end).norm();
+        let angle = ((a * a + b * b - c * c) / (2.0 * a * b)).acos();
+        if angle < std::f64::consts::FRAC_PI_2 {
+            gaps += 1;
+        }
+    }
+
+    let gesture = match gaps {
+        0 => {
+            let mut hull_points = VectorOfPoint::new();
+            convex_hull(contour, &mut hull_points, false, true)?;
+            let solidity = area / contour_area(&hull_points, false)?;
+            if solidity >= FIST_SOLIDITY {
+                Gesture::Fist
+            } else {
+                Gesture::Fingers(1)
+            }
+        }
+        g if g >= 4 => Gesture::OpenPalm,
+        g => Gesture::Fingers(g as u8 + 1),
+    };
+
+    Ok(Some(gesture))
+}
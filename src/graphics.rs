@@ -27,6 +27,13 @@ pub struct Graphics {
     pub font: i32,
     pub score_pos_left: Point,
     pub score_pos_right: Point,
+    pub hits_pos_left: Point,
+    pub hits_pos_right: Point,
+    pub sets_pos_left: Point,
+    pub sets_pos_right: Point,
+    // four players lives, indexed by player (left, right, top, bottom)
+    pub lives_pos: [Point; 4],
+    pub center_pos: Point,
 }
 
 impl Graphics {
@@ -48,6 +55,17 @@ impl Graphics {
             font: DEFAULT_FONT,
             score_pos_left: Point::new(3 * screen.width / 8 as i32, screen.height / 8 as i32),
             score_pos_right: Point::new(5 * screen.width / 8 as i32, screen.height / 8 as i32),
+            hits_pos_left: Point::new(3 * screen.width / 8 as i32, screen.height / 5 as i32),
+            hits_pos_right: Point::new(5 * screen.width / 8 as i32, screen.height / 5 as i32),
+            sets_pos_left: Point::new(3 * screen.width / 8 as i32, screen.height / 4 as i32),
+            sets_pos_right: Point::new(5 * screen.width / 8 as i32, screen.height / 4 as i32),
+            lives_pos: [
+                Point::new(screen.width / 16 as i32, screen.height / 2 as i32),
+                Point::new(7 * screen.width / 8 as i32, screen.height / 2 as i32),
+                Point::new(screen.width / 2 as i32, screen.height / 8 as i32),
+                Point::new(screen.width / 2 as i32, 15 * screen.height / 16 as i32),
+            ],
+            center_pos: Point::new(3 * screen.width / 8 as i32, screen.height / 2 as i32),
         }
     }
 }
@@ -0,0 +1,26 @@
+use crate::game::Command;
+
+const ESCAPE: i32 = 27;
+
+pub fn key_command(key: i32) -> Option<Command> {
+    // game commands bound to the keys pressed in the game window, other keys
+    // are ignored so that a stray key press doesn't end the game
+    if key == ESCAPE {
+        return Some(Command::Quit);
+    }
+    if key <= 0 || key > 127 {
+        return None;
+    }
+    match (key as u8 as char).to_ascii_lowercase() {
+        'p' => Some(Command::Pause),
+        ' ' => Some(Command::Resume),
+        'r' => Some(Command::ResetScore),
+        'b' => Some(Command::ResetBall),
+        'o' => Some(Command::ToggleSolo),
+        'a' => Some(Command::Redetect),
+        '+' => Some(Command::Harder),
+        '-' => Some(Command::Easier),
+        'q' => Some(Command::Quit),
+        _ => None,
+    }
+}
@@ -1,6 +1,22 @@
+pub mod ai;
+pub mod autotune;
+pub mod background;
+pub mod breakout;
 pub mod calibration;
+pub mod config;
 pub mod detection;
+pub mod drift;
 pub mod errors;
+pub mod exclusion;
+pub mod flow;
 pub mod game;
+pub mod gesture;
 pub mod graphics;
+pub mod keyboard;
+pub mod markers;
+pub mod paddles;
+pub mod pipeline;
+pub mod tracking;
+pub mod tuning;
 pub mod utils;
+pub mod verification;
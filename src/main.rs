@@ -1,24 +1,56 @@
 use getopts::{Matches, Options};
 use opencv::{calib3d::*, core::*, highgui::*, imgproc::*, prelude::*, types::*, videoio::*};
 use std::cmp::*;
+use std::path::Path;
 use std::{env, thread, time};
 
+use gnop_pong::autotune::*;
+use gnop_pong::background::*;
+use gnop_pong::breakout::*;
 use gnop_pong::calibration::*;
+use gnop_pong::config::*;
 use gnop_pong::detection::*;
+use gnop_pong::drift::*;
+use gnop_pong::exclusion::*;
+use gnop_pong::flow::*;
 use gnop_pong::game::*;
+use gnop_pong::gesture::*;
+use gnop_pong::keyboard::*;
+use gnop_pong::markers::*;
+use gnop_pong::paddles::*;
+use gnop_pong::pipeline::*;
+use gnop_pong::tracking::*;
+use gnop_pong::tuning::*;
 use gnop_pong::utils::*;
+use gnop_pong::verification::*;
 
 const DEFAULT_SCREEN_WIDTH: i32 = 1920;
 const DEFAULT_SCREEN_HEIGHT: i32 = 1080;
 const DEFAULT_CAM_WIDTH: i32 = 640;
 const DEFAULT_CAM_HEIGHT: i32 = 480;
+const DEFAULT_CAM_FPS: f32 = 30.0;
+const DEFAULT_CONFIG_PATH: &str = "gnop.toml";
+const TRACKING_MAX_DISTANCE: f32 = 100.0; // in game pixels
+const SHAPE_PERSISTENCE: Persistence = Persistence {
+    window: 5,
+    required: 3,
+    grace: 3,
+};
 
 #[derive(Debug)]
 pub struct Args {
     projector_res: Size,
     flag_fullscreen: bool,
     flag_solo: bool,
+    flag_ai: bool,
+    flag_breakout: bool,
+    latency: Option<time::Duration>,
     dbg_level: usize,
+    flag_tuning: bool,
+    verify_level: usize,
+    difficulty: Option<Difficulty>,
+    config: Config,
+    config_path: String,
 }
 
 fn main() {
@@ -37,6 +69,44 @@ fn main() {
         "game projected on full screen, no smaller playing area detection",
     );
     opts.optflag("s", "solo", "single player");
+    opts.optflag(
+        "a",
+        "ai",
+        "play against the computer \n settings from the configuration file if present",
+    );
+    opts.optflag(
+        "b",
+        "breakout",
+        "breakout mode \n levels and settings from the configuration file if present",
+    );
+    opts.optopt(
+        "l",
+        "latency",
+        "projector to camera latency in milliseconds \n measured at startup if not set",
+        "MS",
+    );
+    opts.optopt(
+        "c",
+        "config",
+        "configuration file \n default: gnop.toml if present",
+        "FILE",
+    );
+    opts.optopt(
+        "D",
+        "difficulty",
+        "kids, normal or expert \n overrides the configuration file",
+        "LEVEL",
+    );
+    opts.optflag(
+        "t",
+        "tuning",
+        "detection tuning window \n 's' saves the tuned values to the configuration file",
+    );
+    opts.optflagmulti(
+        "v",
+        "verify",
+        "projection verification \n -v reports the offset and scale errors of the projected ball seen by the camera \n -vv also corrects the detected shapes",
+    );
     opts.optflagmulti("d", "", "debug execution \n -d shows some debug info \n -dd save detected contours's shapes in a video file (MJPG codec)");
     opts.optflag("h", "help", "prints usage");
 
@@ -82,16 +152,58 @@ fn parse_args(matches: &Matches) -> Args {
         None => (DEFAULT_SCREEN_WIDTH, DEFAULT_SCREEN_HEIGHT),
     };
 
+    let config_path = matches
+        .opt_str("c")
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
     let args = Args {
         projector_res: Size { width, height },
         flag_fullscreen: matches.opt_present("f"),
         flag_solo: matches.opt_present("s"),
+        flag_ai: matches.opt_present("a"),
+        flag_breakout: matches.opt_present("b"),
+        latency: matches.opt_str("l").map(|s| match s.parse::<u64>() {
+            Ok(ms) => time::Duration::from_millis(ms),
+            Err(_) => panic!("Invalid latency {}: milliseconds expected", s),
+        }),
         dbg_level: usize::min(2, matches.opt_count("d")),
+        flag_tuning: matches.opt_present("t"),
+        verify_level: usize::min(2, matches.opt_count("v")),
+        difficulty: matches
+            .opt_str("D")
+            .map(|s| match Difficulty::from_name(&s) {
+                Some(d) => d,
+                None => panic!("Unknown difficulty {}: kids, normal or expert expected", s),
+            }),
+        config: load_config(&config_path, matches.opt_present("c")),
+        config_path: config_path,
     };
 
     args
 }
 
+fn load_config(path: &str, required: bool) -> Config {
+    // an explicitly given config file must load, the default one is optional
+    if !required && !Path::new(path).exists() {
+        return Config::default();
+    }
+    match Config::load(Path::new(path)) {
+        Ok(c) => c,
+        Err(r) => panic!("{}: {}", path, r.to_string()),
+    }
+}
+
+fn save_detection(args: &Args, detector: &Detector) {
+    // writes the tuned pipeline to the configuration file
+    let mut config = args.config.clone();
+    if let Detector::Edges(ref p) = *detector {
+        config.detection.pipeline = p.stages().to_vec();
+    }
+    match config.save(Path::new(&args.config_path)) {
+        Ok(()) => println!("Detection settings saved to {}", args.config_path),
+        Err(r) => println!("{}", r.to_string()),
+    }
+}
+
 fn run(args: &Args) -> opencv::Result<()> {
     let projector_res = args.projector_res;
     let camera_res = Size {
@@ -119,10 +231,25 @@ fn run(args: &Args) -> opencv::Result<()> {
     thread::sleep(time::Duration::from_millis(2)); // camera warm up
 
     // projector and playing area detections
-    let (screen, area) = match get_unwarped_areas(&mut cam, projector_res, args.flag_fullscreen) {
-        Ok((s, p)) => (s, p),
-        Err(r) => panic!(r.to_string()),
+    let (mut screen, mut area) =
+        match get_unwarped_areas(&mut cam, projector_res, args.flag_fullscreen) {
+            Ok((s, p)) => (s, p),
+            Err(r) => panic!(r.to_string()),
+        };
+
+    let latency = match args.latency {
+        Some(l) => l,
+        None => match measure_latency(&mut cam, projector_res) {
+            Ok(l) => l,
+            Err(r) => {
+                println!("{}, shapes won't be extrapolated", r.to_string());
+                time::Duration::from_millis(0)
+            }
+        },
     };
+    if args.dbg_level >= 1 {
+        println!("Projector to camera latency: {:?}", latency);
+    }
 
     // game init
     let game_res = Size {
@@ -131,7 +258,54 @@ fn run(args: &Args) -> opencv::Result<()> {
         height: ((area.size.height as f64 / screen.size.height as f64)
             * projector_res.height as f64) as i32,
     };
-    let mut game = Game::new(game_res, args.flag_solo);
+    let paddle_rule = match args.config.markers {
+        Some(ref m) => m.rule,
+        None => PaddleRule::default(),
+    };
+    let mut rules = args.config.rules;
+    if let Some(d) = args.difficulty {
+        rules.difficulty = d;
+    }
+    let ai = match args.config.ai {
+        Some(a) => Some(a),
+        None if args.flag_ai => Some(AiConfig::default()),
+        None => None,
+    };
+    if ai.is_some() && args.flag_solo {
+        // solo mode has no opposing goal for the computer to defend
+        panic!("Solo mode (-s) can't be played against the computer");
+    }
+    if ai.is_some() && rules.players == 4 {
+        panic!("Four players matches can't be played against the computer");
+    }
+    let breakout = match args.config.breakout {
+        Some(ref b) => Some(b.clone()),
+        None if args.flag_breakout => Some(BreakoutConfig::default()),
+        None => None,
+    }
+    .map(|b| {
+        // level files are relative to the configuration file
+        let dir = Path::new(&args.config_path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        match Breakout::new(b, game_res, dir) {
+            Ok(b) => b,
+            Err(r) => panic!("{}", r.to_string()),
+        }
+    });
+    if ai.is_some() && breakout.is_some() {
+        // the breakout player is alone against the bricks
+        panic!("Breakout (-b) can't be played against the computer");
+    }
+    let mut game = Game::new(
+        game_res,
+        args.flag_solo,
+        paddle_rule,
+        rules,
+        args.config.multiball,
+        ai,
+        breakout,
+    );
     if args.dbg_level >= 1 {
         println!(
             "Starting game at {}x{} resolution",
@@ -144,12 +318,12 @@ fn run(args: &Args) -> opencv::Result<()> {
         Mat::zeros(projector_res.height, projector_res.width, CV_8UC3)?.to_mat()?;
     output_mat.set_to(&Scalar::new(0.0, 0.0, 0.0, 0.0), &no_array()?)?;
     let mut game_mat = Mat::zeros(game_res.height, game_res.width, CV_8UC3)?.to_mat()?;
-    let game_roi = get_game_roi(projector_res, game_res, &screen, &area);
+    let mut game_roi = get_game_roi(projector_res, game_res, &screen, &area);
     let mut region = opencv::prelude::Mat::roi(&output_mat, game_roi)?;
 
     // ratio (unwarped image -> game pixels) for shape scaling
-    let x_ratio = (game_res.width as f64 / area.unwarped_size.width as f64) as f64;
-    let y_ratio = (game_res.height as f64 / area.unwarped_size.height as f64) as f64;
+    let mut x_ratio = (game_res.width as f64 / area.unwarped_size.width as f64) as f64;
+    let mut y_ratio = (game_res.height as f64 / area.unwarped_size.height as f64) as f64;
 
     let mut writer = if args.dbg_level == 2 {
         let fourcc = VideoWriter::fourcc('M' as u8, 'J' as u8, 'P' as u8, 'G' as u8)?;
@@ -164,10 +338,167 @@ fn run(args: &Args) -> opencv::Result<()> {
         None
     };
 
-    let mut detection_thresholds = ThreshCacher::new(0.3);
+    let mut detector = match args.config.detection.mode {
+        DetectorMode::Edges => match Pipeline::new(&args.config.detection.pipeline) {
+            Ok(p) => Detector::Edges(p),
+            Err(r) => panic!(r.to_string()),
+        },
+        DetectorMode::Flow => Detector::Flow(FlowDetector::new()?),
+        DetectorMode::Skin => {
+            match SkinDetector::calibrate(&mut cam, &area, projector_res, game_roi) {
+                Ok(s) => Detector::Skin(s),
+                Err(r) => panic!(r.to_string()),
+            }
+        }
+    };
+    let touch_only = args.config.detection.touch_only;
+    let mut background = if args.config.detection.reject_shadows || touch_only {
+        // learnt on the empty area while the (black) game background is projected
+        show_frame("game", &output_mat)?;
+        wait_key(10)?;
+        match Background::learn(&mut cam, &area) {
+            Ok(b) => Some(b),
+            Err(r) => panic!(r.to_string()),
+        }
+    } else {
+        None
+    };
+    let mut mask = match detection_mask(
+        &mut cam,
+        &area,
+        &mut detector,
+        background.as_ref(),
+        &args.config.exclusion,
+    ) {
+        Ok(m) => m,
+        Err(r) => panic!(r.to_string()),
+    };
+    if args.config.detection.autotune {
+        if let Detector::Edges(ref p) = detector {
+            let stages = match autotune(&mut cam, &area, background.as_ref(), &mask, p.stages()) {
+                Ok(s) => s,
+                Err(r) => panic!(r.to_string()),
+            };
+            // only saved from the tuning window, the configuration file is
+            // left untouched otherwise
+            println!(
+                "Auto-tuned detection: {:?}",
+                Parameters::from_stages(&stages)
+            );
+            match Pipeline::new(&stages) {
+                Ok(p) => detector = Detector::Edges(p),
+                Err(r) => panic!(r.to_string()),
+            }
+        }
+    }
+    let mut tracker = ShapeTracker::new(TRACKING_MAX_DISTANCE, SHAPE_PERSISTENCE);
+    let mut markers = match args.config.markers {
+        Some(ref m) => Some(MarkerDetector::new(m.clone())?),
+        None => None,
+    };
+    let mut gestures = if args.config.detection.gestures {
+        Some(GestureRecognizer::new())
+    } else {
+        None
+    };
+    let paddles = match args.config.aruco {
+        Some(ref a) => match PaddleDetector::new(a) {
+            Ok(p) => Some(p),
+            Err(r) => panic!(r.to_string()),
+        },
+        None => None,
+    };
+    let tuning = match detector {
+        Detector::Edges(ref p) if args.flag_tuning => Some(Tuning::open(p.stages())?),
+        _ => None,
+    };
+    let mut verifier = if args.verify_level >= 1 {
+        Some(Verifier::new(x_ratio, y_ratio))
+    } else {
+        None
+    };
+    let mut correction = Correction::identity();
+    let mut drift = {
+        let mut frame = Mat::default()?;
+        cam.read(&mut frame)?;
+        DriftMonitor::new(&frame, &area)?
+    };
+    let mut frame_period = 1.0 / DEFAULT_CAM_FPS;
+    let mut last_frame = time::Instant::now();
+    let mut last_update = time::Instant::now();
     loop {
+        // smoothed loop period, used to convert latency into frames of motion
+        let now = time::Instant::now();
+        frame_period = 0.9 * frame_period + 0.1 * (now - last_frame).as_secs_f32();
+        last_frame = now;
+        tracker.set_lead(latency.as_secs_f32() / frame_period);
+
         let mut frame = Mat::default()?;
         cam.read(&mut frame)?;
+        let redetect = game.take_redetect();
+        if redetect || drift.check(&frame)? {
+            // the camera moved: the geometry is computed again with the game paused,
+            // the game keeps its resolution
+            if !redetect {
+                println!("Camera drift detected, detecting the playing area again");
+            }
+            let paused = game.is_paused();
+            game.command(Command::Pause);
+            game.draw(&mut game_mat)?;
+            game_mat.copy_to(&mut region)?;
+            show_frame("game", &output_mat)?;
+            match get_unwarped_areas(&mut cam, projector_res, args.flag_fullscreen) {
+                Ok((s, a)) => {
+                    screen = s;
+                    area = a;
+                }
+                Err(r) => println!("{}, keeping the previous geometry", r.to_string()),
+            }
+            game_roi = get_game_roi(projector_res, game_res, &screen, &area);
+            game_roi.x = i32::min(game_roi.x, projector_res.width - game_res.width);
+            game_roi.y = i32::min(game_roi.y, projector_res.height - game_res.height);
+            output_mat.set_to(&Scalar::new(0.0, 0.0, 0.0, 0.0), &no_array()?)?;
+            region = opencv::prelude::Mat::roi(&output_mat, game_roi)?;
+            x_ratio = (game_res.width as f64 / area.unwarped_size.width as f64) as f64;
+            y_ratio = (game_res.height as f64 / area.unwarped_size.height as f64) as f64;
+            // the detection mask (and background) are learnt with the black
+            // output projected
+            show_frame("game", &output_mat)?;
+            wait_key(10)?;
+            if background.is_some() {
+                background = match Background::learn(&mut cam, &area) {
+                    Ok(b) => Some(b),
+                    Err(r) => panic!(r.to_string()),
+                };
+            }
+            mask = match detection_mask(
+                &mut cam,
+                &area,
+                &mut detector,
+                background.as_ref(),
+                &args.config.exclusion,
+            ) {
+                Ok(m) => m,
+                Err(r) => panic!(r.to_string()),
+            };
+            tracker = ShapeTracker::new(TRACKING_MAX_DISTANCE, SHAPE_PERSISTENCE);
+            if verifier.is_some() {
+                verifier = Some(Verifier::new(x_ratio, y_ratio));
+                correction = Correction::identity();
+            }
+            cam.read(&mut frame)?;
+            drift = DriftMonitor::new(&frame, &area)?;
+            if !paused {
+                game.command(Command::Resume);
+            }
+            if args.dbg_level >= 1 {
+                println!("Playing area detected again at {:?}", game_roi);
+            }
+            // the time spent detecting is neither simulated nor a frame period
+            last_frame = time::Instant::now();
+            last_update = last_frame;
+            continue;
+        }
 
         let mut undistorted = Mat::default()?;
         undistort(
@@ -177,23 +508,78 @@ fn run(args: &Args) -> opencv::Result<()> {
             &c.distortion_coeffs,
             &optimal_matrix,
         )?;
-        let mut unwarped = Mat::default()?;
-        warp_perspective(
-            &frame,
-            &mut unwarped,
-            &area.unwarped_mat,
-            area.size,
-            INTER_LINEAR,
-            BORDER_CONSTANT,
-            Scalar::default(),
-        )?;
+        let mut unwarped = unwarp(&frame, &area)?;
+        if let Some(ref mut v) = verifier {
+            // the ball drawn last is seen after the projector to camera latency
+            let delay = (latency.as_secs_f32() / frame_period).round() as usize;
+            if let Some(c) = v.update(&unwarped, game.ball_center(), game.ball_radius(), delay)? {
+                println!(
+                    "Projection error: {:.1}px, offset {:.1}x{:.1}px, scale {:.3}x{:.3}",
+                    c.error, c.offset.x, c.offset.y, c.scale.x, c.scale.y
+                );
+                if args.verify_level == 2 {
+                    correction = c;
+                }
+            }
+        }
+        let shadows = match background {
+            Some(ref b) if args.config.detection.reject_shadows => {
+                Some(b.remove_shadows(&mut unwarped)?)
+            }
+            Some(ref b) => Some(b.shadow_mask(&unwarped)?),
+            None => None,
+        };
 
-        let mut scaled_shapes = VectorOfRotatedRect::new();
-        let shapes = shape_detect(&mut unwarped, &mut detection_thresholds)?;
-        for shape in 0..shapes.len() {
-            let shape = shapes.get(shape)?;
-            let scaled = scale_shape(&shape, x_ratio, y_ratio)?;
-            scaled_shapes.push(scaled);
+        let mut scaled_shapes = Vec::new();
+        let contours = detector.detect(&unwarped, &mask)?;
+        if let Some(ref mut g) = gestures {
+            if let Some(command) = g.update(&contours)? {
+                game.command(command);
+            }
+        }
+        let shapes = detector.shapes(&contours)?;
+        if let Some(ref t) = tuning {
+            t.show(&unwarped, &shapes)?;
+            if let Detector::Edges(ref p) = detector {
+                if let Some(stages) = t.tune(p.stages())? {
+                    match Pipeline::new(&stages) {
+                        Ok(p) => detector = Detector::Edges(p),
+                        Err(r) => println!("{}", r.to_string()),
+                    }
+                }
+            }
+        }
+        if let Some(ref mut m) = markers {
+            m.update(&unwarped)?;
+        }
+        // tagged paddles replace the contours of their cards
+        let paddle_shapes = match paddles {
+            Some(ref p) => p.detect(&unwarped)?,
+            None => Vec::new(),
+        };
+        for paddle in paddle_shapes.iter() {
+            scaled_shapes.push(correction.apply(&paddle.scale(x_ratio, y_ratio)));
+        }
+        for index in 0..shapes.len() {
+            let shape = shapes.get(index)?;
+            let mut detected = Shape::from_rect(&shape);
+            if paddle_shapes.iter().any(|p| p.overlap(&detected) > 0.0) {
+                continue;
+            }
+            if let Some(ref m) = markers {
+                detected.owner = m.owner(&detected)?;
+            }
+            if let Some(motion) = detector.motion(&contours, index)? {
+                detected.velocity = motion;
+                detected.measured = true;
+            }
+            match shadows {
+                Some(ref s) if touch_only => {
+                    detected.touching = is_touching(s, &contours, index)?;
+                }
+                _ => {}
+            }
+            scaled_shapes.push(correction.apply(&detected.scale(x_ratio, y_ratio)));
             if args.dbg_level == 2 {
                 let mut sc_vertices: [Point2f; 4] = [
                     Point2f::default(),
@@ -219,16 +605,39 @@ fn run(args: &Args) -> opencv::Result<()> {
             w.write(&unwarped)?;
         }
 
-        game.update(&scaled_shapes)?;
+        let tracked_shapes = tracker.update(&scaled_shapes);
+        // the physics simulates the real time since the last update
+        let now = time::Instant::now();
+        let elapsed = (now - last_update).as_secs_f32();
+        last_update = now;
+        if touch_only {
+            // hovering objects are still tracked but don't hit the ball
+            let touching: Vec<Shape> = tracked_shapes
+                .iter()
+                .filter(|s| s.touching)
+                .cloned()
+                .collect();
+            game.update(&touching, elapsed)?;
+        } else {
+            game.update(tracked_shapes, elapsed)?;
+        }
         game.draw(&mut game_mat)?;
         game_mat.copy_to(&mut region)?;
         show_frame("game", &output_mat)?;
 
         let key = wait_key(10)?;
-        if key > 0 && key != 255 {
+        if tuning.is_some() && key == 's' as i32 {
+            save_detection(args, &detector);
+        } else if let Some(command) = key_command(key) {
+            game.command(command);
+        }
+        if game.should_quit() {
             break;
         }
     }
+    if tuning.is_some() {
+        destroy_frame("tuning")?;
+    }
     destroy_frame("game")?;
     cam.release()?;
     if let Some(mut writer) = writer {
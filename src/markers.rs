@@ -0,0 +1,105 @@
+use opencv::{core::*, imgproc::*, prelude::*};
+
+use crate::config::{HsvRange, MarkerConfig};
+use crate::detection::Shape;
+use crate::game::Player;
+
+pub struct MarkerDetector {
+    config: MarkerConfig,
+    // color mask of each player with a configured marker
+    masks: Vec<(Player, Mat)>,
+}
+
+impl MarkerDetector {
+    pub fn new(config: MarkerConfig) -> opencv::Result<MarkerDetector> {
+        let mut masks = Vec::new();
+        for player in Player::ALL.iter() {
+            if config.range(*player).is_some() {
+                masks.push((*player, Mat::default()?));
+            }
+        }
+        Ok(MarkerDetector {
+            config: config,
+            masks: masks,
+        })
+    }
+
+    pub fn update(&mut self, img: &Mat) -> opencv::Result<()> {
+        // computes the marker color masks of a new (unwarped) frame
+        let mut hsv = Mat::default()?;
+        cvt_color(&img, &mut hsv, COLOR_BGR2HSV, 0)?;
+        for (player, mask) in self.masks.iter_mut() {
+            if let Some(range) = self.config.range(*player) {
+                in_hsv_range(&hsv, range, mask)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn owner(&self, shape: &Shape) -> opencv::Result<Option<Player>> {
+        // player whose marker color covers the most of the shape, if enough
+        let bounds = shape.bounding_rect();
+        let x = i32::max(0, bounds.x);
+        let y = i32::max(0, bounds.y);
+        let (cols, rows) = match self.masks.first() {
+            Some((_, mask)) => (mask.cols(), mask.rows()),
+            None => return Ok(None),
+        };
+        let width = i32::min(cols, bounds.x + bounds.width) - x;
+        let height = i32::min(rows, bounds.y + bounds.height) - y;
+        if width <= 0 || height <= 0 {
+            return Ok(None);
+        }
+        let roi = Rect::new(x, y, width, height);
+        let mut owner: Option<(Player, f64)> = None;
+        for (player, mask) in self.masks.iter() {
+            let covered = coverage(mask, roi)?;
+            if covered < self.config.min_coverage {
+                continue;
+            }
+            match owner {
+                Some((_, c)) if c >= covered => {}
+                _ => owner = Some((*player, covered)),
+            }
+        }
+
+        Ok(owner.map(|(player, _)| player))
+    }
+}
+
+fn coverage(mask: &Mat, roi: Rect) -> opencv::Result<f64> {
+    let region = Mat::roi(mask, roi)?;
+    Ok(count_non_zero(&region)? as f64 / roi.area() as f64)
+}
+
+fn in_hsv_range(hsv: &Mat, range: &HsvRange, mask: &mut Mat) -> opencv::Result<()> {
+    let (low, high) = (range.low, range.high);
+    if low[0] <= high[0] {
+        in_range(
+            hsv,
+            &Scalar::new(low[0] as f64, low[1] as f64, low[2] as f64, 0.0),
+            &Scalar::new(high[0] as f64, high[1] as f64, high[2] as f64, 0.0),
+            mask,
+        )?;
+    } else {
+        // hue range wraps around 180
+        let mut upper = Mat::default()?;
+        let mut lower = Mat::default()?;
+        in_range(
+            hsv,
+            &Scalar::new(low[0] as f64, low[1] as f64, low[2] as f64, 0.0),
+            &Scalar::new(180.0, high[1] as f64, high[2] as f64, 0.0),
+            &mut upper,
+        )?;
+        in_range(
+            hsv,
+            &Scalar::new(0.0, low[1] as f64, low[2] as f64, 0.0),
+            &Scalar::new(high[0] as f64, high[1] as f64, high[2] as f64, 0.0),
+            &mut lower,
+        )?;
+        bitwise_or(&upper, &lower, mask, &no_array()?)?;
+    }
+
+    Ok(())
+}
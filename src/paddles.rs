@@ -0,0 +1,96 @@
+use opencv::{aruco::*, core::*, prelude::*, types::*};
+
+use crate::config::{ArucoConfig, PaddleConfig};
+use crate::detection::Shape;
+use crate::errors::Error;
+
+pub struct PaddleDetector {
+    dictionary: Ptr<Dictionary>,
+    parameters: Ptr<DetectorParameters>,
+    paddles: Vec<PaddleConfig>,
+}
+
+impl PaddleDetector {
+    pub fn new(config: &ArucoConfig) -> Result<PaddleDetector, Error> {
+        let name = match config.dictionary.as_str() {
+            "4x4_50" => PREDEFINED_DICTIONARY_NAME::DICT_4X4_50,
+            "4x4_100" => PREDEFINED_DICTIONARY_NAME::DICT_4X4_100,
+            "5x5_50" => PREDEFINED_DICTIONARY_NAME::DICT_5X5_50,
+            "5x5_100" => PREDEFINED_DICTIONARY_NAME::DICT_5X5_100,
+            "6x6_50" => PREDEFINED_DICTIONARY_NAME::DICT_6X6_50,
+            "6x6_100" => PREDEFINED_DICTIONARY_NAME::DICT_6X6_100,
+            "original" => PREDEFINED_DICTIONARY_NAME::DICT_ARUCO_ORIGINAL,
+            d => return Err(Error::Config(format!("unknown aruco dictionary {}", d))),
+        };
+
+        Ok(PaddleDetector {
+            dictionary: get_predefined_dictionary(name)?,
+            parameters: DetectorParameters::create()?,
+            paddles: config.paddles.clone(),
+        })
+    }
+
+    pub fn detect(&self, img: &Mat) -> opencv::Result<Vec<Shape>> {
+        // paddles built from the pose of their configured markers in the (unwarped) frame
+        let mut corners = VectorOfVectorOfPoint2f::new();
+        let mut ids = VectorOfi32::new();
+        let mut rejected = VectorOfVectorOfPoint2f::new();
+        detect_markers(
+            img,
+            &self.dictionary,
+            &mut corners,
+            &mut ids,
+            &self.parameters,
+            &mut rejected,
+            &no_array()?,
+            &no_array()?,
+        )?;
+
+        let mut shapes = Vec::new();
+        for index in 0..ids.len() {
+            let id = ids.get(index)?;
+            if let Some(paddle) = self.paddles.iter().find(|p| p.id == id) {
+                let c = corners.get(index)?;
+                shapes.push(paddle_shape(
+                    paddle,
+                    [c.get(0)?, c.get(1)?, c.get(2)?, c.get(3)?],
+                ));
+            }
+        }
+
+        Ok(shapes)
+    }
+}
+
+fn paddle_shape(paddle: &PaddleConfig, c: [Point2f; 4]) -> Shape {
+    // marker corners are clockwise from its top left corner
+    let x_axis = Point2f::new(
+        (c[1].x - c[0].x + c[2].x - c[3].x) / 2.0,
+        (c[1].y - c[0].y + c[2].y - c[3].y) / 2.0,
+    );
+    let y_axis = Point2f::new(
+        (c[3].x - c[0].x + c[2].x - c[1].x) / 2.0,
+        (c[3].y - c[0].y + c[2].y - c[1].y) / 2.0,
+    );
+    let marker_center = Point2f::new(
+        (c[0].x + c[1].x + c[2].x + c[3].x) / 4.0,
+        (c[0].y + c[1].y + c[2].y + c[3].y) / 4.0,
+    );
+    let center = Point2f::new(
+        marker_center.x + paddle.offset[0] * x_axis.x + paddle.offset[1] * y_axis.x,
+        marker_center.y + paddle.offset[0] * x_axis.y + paddle.offset[1] * y_axis.y,
+    );
+
+    Shape {
+        center: center,
+        size: Size2f::new(
+            paddle.width * x_axis.norm() as f32,
+            paddle.height * y_axis.norm() as f32,
+        ),
+        angle: x_axis.y.atan2(x_axis.x).to_degrees(),
+        velocity: Point2f::default(),
+        measured: false,
+        owner: paddle.player,
+        touching: true,
+    }
+}
@@ -0,0 +1,324 @@
+use opencv::{core::*, imgproc::*, prelude::*, types::*};
+
+use crate::config::{ColorSpace, ContourMode, Reducer, Stage, ThresholdKind};
+use crate::detection::{apply_mask, ThreshCacher};
+use crate::errors::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Parameters {
+    pub sigma: f64,
+    pub blur: i32,
+    pub min_area: f64,
+}
+
+impl Parameters {
+    pub fn from_stages(stages: &[Stage]) -> Parameters {
+        // values of the first canny, blur and contours stages
+        let mut parameters = Parameters {
+            sigma: 0.0,
+            blur: 1,
+            min_area: 0.0,
+        };
+        for stage in stages.iter().rev() {
+            match *stage {
+                Stage::Canny { sigma } => parameters.sigma = sigma,
+                Stage::Blur { kernel } => parameters.blur = kernel,
+                Stage::Contours { min_area, .. } => parameters.min_area = min_area,
+                _ => {}
+            }
+        }
+        parameters
+    }
+
+    pub fn apply(&self, stages: &[Stage]) -> Vec<Stage> {
+        stages
+            .iter()
+            .map(|stage| match *stage {
+                Stage::Canny { .. } => Stage::Canny { sigma: self.sigma },
+                Stage::Blur { .. } => Stage::Blur { kernel: self.blur },
+                Stage::Contours { mode, .. } => Stage::Contours {
+                    mode: mode,
+                    min_area: self.min_area,
+                },
+                ref s => s.clone(),
+            })
+            .collect()
+    }
+}
+
+// image passed between the stages
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Color,
+    Gray,
+    Binary,
+}
+
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    // cached canny thresholds, one per stage
+    thresholds: Vec<Option<ThreshCacher>>,
+}
+
+impl Pipeline {
+    pub fn new(stages: &[Stage]) -> Result<Pipeline, Error> {
+        Pipeline::validate(stages)?;
+
+        Ok(Pipeline {
+            thresholds: stages
+                .iter()
+                .map(|s| match *s {
+                    Stage::Canny { sigma } => Some(ThreshCacher::new(sigma)),
+                    _ => None,
+                })
+                .collect(),
+            stages: stages.to_vec(),
+        })
+    }
+
+    pub fn validate(stages: &[Stage]) -> Result<(), Error> {
+        // image stages, then a single contours stage optionally followed by a reducer
+        let contours = match stages
+            .iter()
+            .position(|s| matches!(s, Stage::Contours { .. }))
+        {
+            Some(c) => c,
+            None => return Err(invalid("no contours stage")),
+        };
+        for (index, stage) in stages.iter().enumerate() {
+            match *stage {
+                Stage::Contours { .. } if index != contours => {
+                    return Err(invalid("more than one contours stage"))
+                }
+                Stage::Reduce { .. } if index < contours => {
+                    return Err(invalid("reduce stage before the contours stage"))
+                }
+                Stage::Contours { .. } | Stage::Reduce { .. } => {}
+                _ if index > contours => {
+                    return Err(invalid("image stage after the contours stage"))
+                }
+                Stage::Blur { kernel }
+                | Stage::MedianBlur { kernel }
+                | Stage::Open { kernel }
+                | Stage::Close { kernel }
+                    if kernel <= 0 || kernel % 2 == 0 =>
+                {
+                    return Err(invalid("kernel sizes must be odd and positive"))
+                }
+                Stage::Threshold {
+                    kind: ThresholdKind::Adaptive,
+                    block,
+                    ..
+                } if block <= 1 || block % 2 == 0 => {
+                    return Err(invalid("adaptive threshold blocks must be odd and above 1"))
+                }
+                _ => {}
+            }
+        }
+
+        // the camera image is in colour, each stage needs a compatible input
+        let mut kind = Kind::Color;
+        for stage in stages[..=contours].iter() {
+            kind = match (stage, kind) {
+                (
+                    Stage::Convert {
+                        to: ColorSpace::Gray,
+                    },
+                    Kind::Color,
+                ) => Kind::Gray,
+                (Stage::Convert { .. }, Kind::Color) => Kind::Color,
+                (Stage::Convert { .. }, _) => {
+                    return Err(invalid("convert stage on a single channel image"))
+                }
+                (Stage::InRange { .. }, Kind::Color) => Kind::Binary,
+                (Stage::InRange { .. }, _) => {
+                    return Err(invalid("in_range stage on a single channel image"))
+                }
+                // blurring a binary image makes it gray
+                (Stage::Blur { .. }, Kind::Binary) | (Stage::MedianBlur { .. }, Kind::Binary) => {
+                    Kind::Gray
+                }
+                (Stage::Blur { .. }, k) | (Stage::MedianBlur { .. }, k) => k,
+                (Stage::Open { .. }, k) | (Stage::Close { .. }, k) => k,
+                (Stage::Canny { .. }, Kind::Color) | (Stage::Threshold { .. }, Kind::Color) => {
+                    return Err(invalid(
+                        "canny and threshold stages need a gray image (convert to gray first)",
+                    ))
+                }
+                (Stage::Canny { .. }, _) | (Stage::Threshold { .. }, _) => Kind::Binary,
+                (Stage::Contours { .. }, Kind::Binary) => Kind::Binary,
+                (Stage::Contours { .. }, _) => {
+                    return Err(invalid(
+                        "contours stage needs a binary image (canny, threshold, in_range)",
+                    ))
+                }
+                (Stage::Reduce { .. }, k) => k,
+            };
+        }
+
+        Ok(())
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    pub fn reducer(&self) -> Reducer {
+        self.stages
+            .iter()
+            .filter_map(|s| match *s {
+                Stage::Reduce { shape } => Some(shape),
+                _ => None,
+            })
+            .next()
+            .unwrap_or(Reducer::MinAreaRect)
+    }
+
+    pub fn detect(&mut self, img: &Mat, mask: &Mat) -> opencv::Result<VectorOfVectorOfPoint> {
+        let mut current: Option<Mat> = None;
+        for (stage, thresholds) in self.stages.iter().zip(self.thresholds.iter_mut()) {
+            let src = current.as_ref().unwrap_or(img);
+            let mut dst = Mat::default()?;
+            match *stage {
+                Stage::Convert { to } => {
+                    let code = match to {
+                        ColorSpace::Gray => COLOR_BGR2GRAY,
+                        ColorSpace::Hsv => COLOR_BGR2HSV,
+                        ColorSpace::Ycrcb => COLOR_BGR2YCrCb,
+                    };
+                    cvt_color(src, &mut dst, code, 0)?;
+                }
+                Stage::InRange { low, high } => {
+                    in_range(
+                        src,
+                        &Scalar::new(low[0] as f64, low[1] as f64, low[2] as f64, 0.0),
+                        &Scalar::new(high[0] as f64, high[1] as f64, high[2] as f64, 0.0),
+                        &mut dst,
+                    )?;
+                }
+                Stage::Blur { kernel } => {
+                    gaussian_blur(
+                        src,
+                        &mut dst,
+                        Size::new(kernel, kernel),
+                        0.0,
+                        0.0,
+                        BORDER_DEFAULT,
+                    )?;
+                }
+                Stage::MedianBlur { kernel } => {
+                    median_blur(src, &mut dst, kernel)?;
+                }
+                Stage::Canny { .. } => {
+                    let (threshold_min, threshold_max) = match thresholds {
+                        Some(t) => t.values(src),
+                        None => (100.0, 200.0),
+                    };
+                    canny(src, &mut dst, threshold_min, threshold_max, 3, false)?;
+                }
+                Stage::Threshold { kind, value, block } => match kind {
+                    ThresholdKind::Binary => {
+                        threshold(src, &mut dst, value, 255.0, THRESH_BINARY)?;
+                    }
+                    ThresholdKind::BinaryInv => {
+                        threshold(src, &mut dst, value, 255.0, THRESH_BINARY_INV)?;
+                    }
+                    ThresholdKind::Otsu => {
+                        threshold(src, &mut dst, 0.0, 255.0, THRESH_BINARY | THRESH_OTSU)?;
+                    }
+                    ThresholdKind::Adaptive => {
+                        adaptive_threshold(
+                            src,
+                            &mut dst,
+                            255.0,
+                            ADAPTIVE_THRESH_GAUSSIAN_C,
+                            THRESH_BINARY,
+                            block,
+                            value,
+                        )?;
+                    }
+                },
+                Stage::Open { kernel } => morphology(src, &mut dst, MORPH_OPEN, kernel)?,
+                Stage::Close { kernel } => morphology(src, &mut dst, MORPH_CLOSE, kernel)?,
+                Stage::Contours { mode, min_area } => {
+                    let mut binary = Mat::default()?;
+                    src.copy_to(&mut binary)?;
+                    apply_mask(&mut binary, mask)?;
+                    let mode = match mode {
+                        ContourMode::External => RETR_EXTERNAL,
+                        ContourMode::List => RETR_LIST,
+                    };
+                    let mut found = VectorOfVectorOfPoint::new();
+                    find_contours(
+                        &binary,
+                        &mut found,
+                        mode,
+                        CHAIN_APPROX_SIMPLE,
+                        Point::new(0, 0),
+                    )?;
+                    let mut contours = VectorOfVectorOfPoint::new();
+                    for index in 0..found.len() {
+                        let c = found.get(index)?;
+                        if min_area <= 0.0 || contour_area(&c, false)? >= min_area {
+                            contours.push(c);
+                        }
+                    }
+                    return Ok(contours);
+                }
+                Stage::Reduce { .. } => {}
+            }
+            current = Some(dst);
+        }
+
+        Ok(VectorOfVectorOfPoint::new())
+    }
+}
+
+pub fn reduce_contours(
+    contours: &VectorOfVectorOfPoint,
+    reducer: Reducer,
+) -> opencv::Result<VectorOfRotatedRect> {
+    let mut rect = VectorOfRotatedRect::new();
+    for index in 0..contours.len() {
+        let c = contours.get(index)?;
+        let reduced = match reducer {
+            Reducer::MinAreaRect => min_area_rect(&c)?,
+            Reducer::BoundingRect => {
+                let r = bounding_rect(&c)?;
+                RotatedRect::new(
+                    Point2f::new(
+                        r.x as f32 + r.width as f32 / 2.0,
+                        r.y as f32 + r.height as f32 / 2.0,
+                    ),
+                    Size2f::new(r.width as f32, r.height as f32),
+                    0.0,
+                )?
+            }
+            // an ellipse needs at least 5 points
+            Reducer::Ellipse if c.len() >= 5 => fit_ellipse(&c)?,
+            Reducer::Ellipse => min_area_rect(&c)?,
+        };
+        rect.push(reduced);
+    }
+
+    Ok(rect)
+}
+
+fn morphology(src: &Mat, dst: &mut Mat, op: i32, kernel: i32) -> opencv::Result<()> {
+    let kernel =
+        get_structuring_element(MORPH_ELLIPSE, Size::new(kernel, kernel), Point::new(-1, -1))?;
+    morphology_ex(
+        src,
+        dst,
+        op,
+        &kernel,
+        Point::new(-1, -1),
+        1,
+        BORDER_CONSTANT,
+        morphology_default_border_value()?,
+    )
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Config(format!("invalid detection pipeline: {}", reason))
+}
@@ -0,0 +1,220 @@
+use opencv::core::*;
+
+use crate::detection::Shape;
+
+// weight of the newest displacement in the smoothed velocity
+const VELOCITY_SMOOTHING: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Persistence {
+    // number of recent frames considered
+    pub window: u32,
+    // frames of the window a shape must appear in to be collidable
+    pub required: u32,
+    // frames a collidable shape survives after vanishing
+    pub grace: u32,
+}
+
+impl Persistence {
+    fn window_mask(&self) -> u32 {
+        (1u32 << self.window) - 1
+    }
+}
+
+struct Track {
+    shape: Shape,
+    // one bit per frame of the window, lowest bit is the current frame
+    history: u32,
+    missed: u32,
+    // seen in enough frames of the window when last detected
+    confirmed: bool,
+}
+
+impl Track {
+    fn confirm(&mut self, persistence: &Persistence) {
+        self.confirmed =
+            (self.history & persistence.window_mask()).count_ones() >= persistence.required;
+    }
+
+    fn collidable(&self, persistence: &Persistence) -> bool {
+        // a vanished shape keeps its state for the whole grace period, the
+        // missed frames don't count against it
+        self.confirmed && self.missed <= persistence.grace
+    }
+}
+
+pub struct ShapeTracker {
+    tracks: Vec<Track>,
+    collidable: Vec<Shape>,
+    max_distance: f32,
+    persistence: Persistence,
+    // frames the collidable shapes are extrapolated forward to compensate latency
+    lead: f32,
+}
+
+impl ShapeTracker {
+    pub fn new(max_distance: f32, persistence: Persistence) -> ShapeTracker {
+        // the window is held in the bits of a u32
+        assert!(
+            persistence.window >= 1 && persistence.window <= 31,
+            "persistence window must be within 1..=31 frames"
+        );
+        ShapeTracker {
+            tracks: Vec::new(),
+            collidable: Vec::new(),
+            max_distance: max_distance,
+            persistence: persistence,
+            lead: 0.0,
+        }
+    }
+
+    pub fn set_lead(&mut self, frames: f32) {
+        self.lead = frames;
+    }
+
+    pub fn update(&mut self, detected: &[Shape]) -> &[Shape] {
+        // matches each detected shape with the previous shape it overlaps the most
+        // (or the nearest one if none overlaps) and derives its velocity (in pixels
+        // per frame) from the displacement, unless measured by the detector
+        let mut matched = vec![false; self.tracks.len()];
+        let mut new_tracks = Vec::new();
+        for shape in detected {
+            let mut shape = *shape;
+            let mut best: Option<(usize, f32, f32)> = None;
+            for (i, track) in self.tracks.iter().enumerate() {
+                if matched[i] {
+                    continue;
+                }
+                let overlap = shape.overlap(&track.shape);
+                let distance = (shape.center - track.shape.center).norm() as f32;
+                if overlap <= 0.0 && distance > self.max_distance {
+                    continue;
+                }
+                match best {
+                    Some((_, o, d)) if o > overlap || (o == overlap && d <= distance) => {}
+                    _ => best = Some((i, overlap, distance)),
+                }
+            }
+            match best {
+                Some((i, _, _)) => {
+                    matched[i] = true;
+                    let track = &mut self.tracks[i];
+                    // measured motion (optical flow) is preferred to the displacement
+                    let displacement = if shape.measured {
+                        shape.velocity
+                    } else {
+                        shape.center - track.shape.center
+                    };
+                    shape.velocity = Point2f::new(
+                        VELOCITY_SMOOTHING * displacement.x
+                            + (1.0 - VELOCITY_SMOOTHING) * track.shape.velocity.x,
+                        VELOCITY_SMOOTHING * displacement.y
+                            + (1.0 - VELOCITY_SMOOTHING) * track.shape.velocity.y,
+                    );
+                    if shape.owner.is_none() {
+                        // markers may be hidden for a few frames
+                        shape.owner = track.shape.owner;
+                    }
+                    track.shape = shape;
+                }
+                None => {
+                    let mut track = Track {
+                        shape: shape,
+                        history: 1,
+                        missed: 0,
+                        confirmed: false,
+                    };
+                    track.confirm(&self.persistence);
+                    new_tracks.push(track);
+                }
+            }
+        }
+
+        let window_mask = self.persistence.window_mask();
+        for (track, matched) in self.tracks.iter_mut().zip(matched) {
+            track.history <<= 1;
+            if matched {
+                track.history |= 1;
+                track.missed = 0;
+                track.confirm(&self.persistence);
+            } else {
+                track.missed += 1;
+            }
+        }
+        self.tracks.extend(new_tracks);
+        let grace = self.persistence.grace;
+        self.tracks
+            .retain(|t| t.history & window_mask != 0 || t.missed <= grace);
+
+        self.collidable.clear();
+        for track in self.tracks.iter() {
+            if track.collidable(&self.persistence) {
+                let mut shape = track.shape;
+                shape.center.x += shape.velocity.x * self.lead;
+                shape.center.y += shape.velocity.y * self.lead;
+                self.collidable.push(shape);
+            }
+        }
+
+        &self.collidable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERSISTENCE: Persistence = Persistence {
+        window: 5,
+        required: 3,
+        grace: 3,
+    };
+
+    fn shape() -> Shape {
+        Shape {
+            center: Point2f::new(100.0, 100.0),
+            size: Size2f::new(20.0, 20.0),
+            angle: 0.0,
+            velocity: Point2f::default(),
+            measured: false,
+            owner: None,
+            touching: true,
+        }
+    }
+
+    fn collidable(seen: &[bool]) -> Vec<bool> {
+        // collidable state after each frame, the shape being detected or not
+        let mut tracker = ShapeTracker::new(50.0, PERSISTENCE);
+        seen.iter()
+            .map(|s| {
+                let detected = if *s { vec![shape()] } else { Vec::new() };
+                !tracker.update(&detected).is_empty()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn confirmed_after_required_frames() {
+        assert_eq!(collidable(&[true; 3]), vec![false, false, true]);
+    }
+
+    #[test]
+    fn survives_the_whole_grace_period() {
+        let mut seen = vec![true; 5];
+        seen.extend(&[false; 4]);
+        assert_eq!(&collidable(&seen)[5..], &[true, true, true, false]);
+    }
+
+    #[test]
+    fn barely_confirmed_shape_gets_grace() {
+        // seen in exactly 3 of the last 5 frames before vanishing
+        let seen = [true, false, true, false, true, false, false];
+        assert_eq!(&collidable(&seen)[4..], &[true, true, true]);
+    }
+
+    #[test]
+    fn flickering_shape_is_ignored() {
+        let seen = [true, false, false, false, true, false];
+        assert!(collidable(&seen).iter().all(|c| !c));
+    }
+}
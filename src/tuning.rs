@@ -0,0 +1,81 @@
+use opencv::{core::*, highgui::*, imgproc::*, prelude::*, types::*};
+
+use crate::config::Stage;
+use crate::pipeline::Parameters;
+
+const TUNING_WINDOW: &str = "tuning";
+const SIGMA_TRACKBAR: &str = "canny sigma (%)";
+const BLUR_TRACKBAR: &str = "blur (2n+1)";
+const MIN_AREA_TRACKBAR: &str = "min area";
+const MAX_MIN_AREA: i32 = 2000;
+
+pub struct Tuning {
+    // trackbar values, boxed as highgui keeps pointers to them
+    values: Box<[i32; 3]>,
+}
+
+impl Tuning {
+    pub fn open(stages: &[Stage]) -> opencv::Result<Tuning> {
+        // operator window with trackbars for the main parameters of the pipeline
+        named_window(TUNING_WINDOW, WINDOW_NORMAL)?;
+        let parameters = Parameters::from_stages(stages);
+        let mut tuning = Tuning {
+            values: Box::new([
+                (parameters.sigma * 100.0) as i32,
+                (parameters.blur - 1) / 2,
+                parameters.min_area as i32,
+            ]),
+        };
+        let values = &mut *tuning.values;
+        create_trackbar(SIGMA_TRACKBAR, TUNING_WINDOW, &mut values[0], 100, None)?;
+        create_trackbar(BLUR_TRACKBAR, TUNING_WINDOW, &mut values[1], 10, None)?;
+        create_trackbar(
+            MIN_AREA_TRACKBAR,
+            TUNING_WINDOW,
+            &mut values[2],
+            MAX_MIN_AREA,
+            None,
+        )?;
+
+        Ok(tuning)
+    }
+
+    pub fn tune(&self, stages: &[Stage]) -> opencv::Result<Option<Vec<Stage>>> {
+        // stages with the trackbar values, if they changed anything
+        let parameters = Parameters {
+            sigma: get_trackbar_pos(SIGMA_TRACKBAR, TUNING_WINDOW)? as f64 / 100.0,
+            blur: 2 * get_trackbar_pos(BLUR_TRACKBAR, TUNING_WINDOW)? + 1,
+            min_area: get_trackbar_pos(MIN_AREA_TRACKBAR, TUNING_WINDOW)? as f64,
+        };
+        let tuned = parameters.apply(stages);
+        if tuned.as_slice() == stages {
+            return Ok(None);
+        }
+
+        Ok(Some(tuned))
+    }
+
+    pub fn show(&self, unwarped: &Mat, shapes: &VectorOfRotatedRect) -> opencv::Result<()> {
+        // camera view of the playing area with the detected shapes
+        let mut overlay = Mat::default()?;
+        unwarped.copy_to(&mut overlay)?;
+        for index in 0..shapes.len() {
+            let mut vertices = [Point2f::default(); 4];
+            shapes.get(index)?.points(&mut vertices)?;
+            for i in 0..4 {
+                line(
+                    &mut overlay,
+                    vertices[i].to::<i32>().unwrap(),
+                    vertices[(i + 1) % 4].to::<i32>().unwrap(),
+                    Scalar::new(0.0, 255.0, 0.0, 0.0),
+                    2,
+                    LINE_8,
+                    0,
+                )?;
+            }
+        }
+        imshow(TUNING_WINDOW, &overlay)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use opencv::{core::*, imgproc::*, prelude::*, types::*};
+use std::collections::VecDeque;
+
+use crate::detection::Shape;
+
+// half size of the window searched around the expected ball, in camera pixels
+const VERIFY_SEARCH: i32 = 80;
+// the detected blob area must be within this ratio of the projected ball area
+const VERIFY_AREA_RATIO: f64 = 4.0;
+// expected ball positions remembered to match the delayed camera view
+const VERIFY_HISTORY: usize = 30;
+// samples kept for the fit, and frames between two fits
+const VERIFY_SAMPLES: usize = 300;
+const VERIFY_PERIOD: usize = 60;
+const VERIFY_MIN_SAMPLES: usize = 30;
+// below this spread of expected positions (in game pixels) the scale can't be fitted
+const VERIFY_MIN_SPREAD: f32 = 50.0;
+// fits with a scale outside this range or a larger mean residual (in game
+// pixels) are mismatches rather than a projection error, and are dropped
+const VERIFY_SCALE_RANGE: (f32, f32) = (0.5, 2.0);
+const VERIFY_MAX_RESIDUAL: f32 = 20.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Correction {
+    // observed = scale * expected + offset, for each axis, in game pixels
+    pub scale: Point2f,
+    pub offset: Point2f,
+    // mean distance between observed and expected ball, before correction
+    pub error: f32,
+}
+
+impl Correction {
+    pub fn identity() -> Correction {
+        Correction {
+            scale: Point2f::new(1.0, 1.0),
+            offset: Point2f::new(0.0, 0.0),
+            error: 0.0,
+        }
+    }
+
+    pub fn apply(&self, shape: &Shape) -> Shape {
+        // brings a detected shape back into the projected game geometry
+        let mut shape = *shape;
+        shape.center = shape.center - self.offset;
+        shape.scale(1.0 / self.scale.x as f64, 1.0 / self.scale.y as f64)
+    }
+}
+
+pub struct Verifier {
+    // unwarped camera image -> game pixels
+    x_ratio: f64,
+    y_ratio: f64,
+    // newest expected ball center first, in game pixels
+    expected: VecDeque<Point2f>,
+    // (expected, observed) ball centers, in game pixels
+    samples: VecDeque<(Point2f, Point2f)>,
+    frames: usize,
+}
+
+impl Verifier {
+    pub fn new(x_ratio: f64, y_ratio: f64) -> Verifier {
+        Verifier {
+            x_ratio: x_ratio,
+            y_ratio: y_ratio,
+            expected: VecDeque::with_capacity(VERIFY_HISTORY),
+            samples: VecDeque::with_capacity(VERIFY_SAMPLES),
+            frames: 0,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        unwarped: &Mat,
+        ball: Point,
+        radius: i32,
+        delay: usize,
+    ) -> opencv::Result<Option<Correction>> {
+        // the camera sees the ball drawn `delay` frames ago: its projection is
+        // located around that position and compared with it, a plausible fit of
+        // the differences is returned periodically
+        if self.expected.len() == VERIFY_HISTORY {
+            self.expected.pop_back();
+        }
+        self.expected
+            .push_front(Point2f::new(ball.x as f32, ball.y as f32));
+        let expected = match self.expected.get(delay) {
+            Some(e) => *e,
+            None => return Ok(None),
+        };
+
+        let camera_radius = radius as f64 / self.x_ratio;
+        if let Some(observed) = self.locate(unwarped, expected, camera_radius)? {
+            if self.samples.len() == VERIFY_SAMPLES {
+                self.samples.pop_back();
+            }
+            self.samples.push_front((expected, observed));
+        }
+
+        self.frames += 1;
+        if self.frames % VERIFY_PERIOD != 0 || self.samples.len() < VERIFY_MIN_SAMPLES {
+            return Ok(None);
+        }
+
+        Ok(self.fit())
+    }
+
+    fn locate(
+        &self,
+        unwarped: &Mat,
+        expected: Point2f,
+        camera_radius: f64,
+    ) -> opencv::Result<Option<Point2f>> {
+        // brightest blob of the ball size in a window around the expected position
+        let x = (expected.x as f64 / self.x_ratio) as i32;
+        let y = (expected.y as f64 / self.y_ratio) as i32;
+        let left = i32::max(0, x - VERIFY_SEARCH);
+        let top = i32::max(0, y - VERIFY_SEARCH);
+        let right = i32::min(unwarped.cols(), x + VERIFY_SEARCH);
+        let bottom = i32::min(unwarped.rows(), y + VERIFY_SEARCH);
+        if right <= left || bottom <= top {
+            return Ok(None);
+        }
+        let window = Rect::new(left, top, right - left, bottom - top);
+
+        let mut gray = Mat::default()?;
+        cvt_color(&Mat::roi(unwarped, window)?, &mut gray, COLOR_BGR2GRAY, 0)?;
+        let mut bright = Mat::default()?;
+        threshold(&gray, &mut bright, 0.0, 255.0, THRESH_BINARY | THRESH_OTSU)?;
+        let mut contours = VectorOfVectorOfPoint::new();
+        find_contours(
+            &bright,
+            &mut contours,
+            RETR_EXTERNAL,
+            CHAIN_APPROX_SIMPLE,
+            Point::new(left, top),
+        )?;
+
+        let ball_area = std::f64::consts::PI * camera_radius * camera_radius;
+        let mut best: Option<(Point2f, f64)> = None;
+        for index in 0..contours.len() {
+            let c = contours.get(index)?;
+            let area = contour_area(&c, false)?;
+            if area < ball_area / VERIFY_AREA_RATIO || area > ball_area * VERIFY_AREA_RATIO {
+                continue;
+            }
+            match best {
+                Some((_, a)) if a >= area => {}
+                _ => best = Some((min_area_rect(&c)?.center(), area)),
+            }
+        }
+
+        Ok(best.map(|(center, _)| {
+            Point2f::new(
+                center.x * self.x_ratio as f32,
+                center.y * self.y_ratio as f32,
+            )
+        }))
+    }
+
+    fn fit(&self) -> Option<Correction> {
+        // least squares line for each axis, offset only when the ball didn't
+        // travel enough to tell a scale error, None when the fit is implausible
+        let n = self.samples.len() as f32;
+        let mut error = 0.0;
+        let mut mean_e = Point2f::new(0.0, 0.0);
+        let mut mean_o = Point2f::new(0.0, 0.0);
+        for (e, o) in self.samples.iter() {
+            error += (*o - *e).norm() as f32;
+            mean_e = mean_e + *e;
+            mean_o = mean_o + *o;
+        }
+        mean_e = Point2f::new(mean_e.x / n, mean_e.y / n);
+        mean_o = Point2f::new(mean_o.x / n, mean_o.y / n);
+
+        let mut var = Point2f::new(0.0, 0.0);
+        let mut cov = Point2f::new(0.0, 0.0);
+        for (e, o) in self.samples.iter() {
+            var.x += (e.x - mean_e.x) * (e.x - mean_e.x);
+            var.y += (e.y - mean_e.y) * (e.y - mean_e.y);
+            cov.x += (e.x - mean_e.x) * (o.x - mean_o.x);
+            cov.y += (e.y - mean_e.y) * (o.y - mean_o.y);
+        }
+        let min_var = n * VERIFY_MIN_SPREAD * VERIFY_MIN_SPREAD;
+        let scale = Point2f::new(
+            if var.x >= min_var { cov.x / var.x } else { 1.0 },
+            if var.y >= min_var { cov.y / var.y } else { 1.0 },
+        );
+
+        let (min_scale, max_scale) = VERIFY_SCALE_RANGE;
+        let plausible = |s: f32| s >= min_scale && s <= max_scale;
+        if !plausible(scale.x) || !plausible(scale.y) {
+            return None;
+        }
+        let offset = Point2f::new(mean_o.x - scale.x * mean_e.x, mean_o.y - scale.y * mean_e.y);
+
+        let mut residual = 0.0;
+        for (e, o) in self.samples.iter() {
+            let fitted = Point2f::new(scale.x * e.x + offset.x, scale.y * e.y + offset.y);
+            residual += (*o - fitted).norm() as f32;
+        }
+        if residual / n > VERIFY_MAX_RESIDUAL {
+            return None;
+        }
+
+        Some(Correction {
+            offset: offset,
+            scale: scale,
+            error: error / n,
+        })
+    }
+}